@@ -2,10 +2,11 @@
 use core::fmt;
 use std::{
     fs::File,
+    io::BufWriter,
     iter::zip,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, RwLock,
+        Arc, Mutex, RwLock,
     },
     thread,
     time::Duration,
@@ -13,22 +14,29 @@ use std::{
 
 use chrono::TimeDelta;
 use color_eyre::Result;
-use crossterm::event::{Event, KeyCode, KeyEvent};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
 use futures::StreamExt;
 use indexmap::IndexMap;
-use quanta::Instant;
 use ratatui::{
     crossterm::event::EventStream,
+    layout::{Constraint, Layout},
     text::{self, Text, ToText},
-    widgets::Paragraph,
+    widgets::{Block, Paragraph},
     DefaultTerminal,
 };
-use tokio::{task::JoinSet, time::MissedTickBehavior};
+use tokio::{sync::Notify, task::JoinSet, time::MissedTickBehavior};
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info, instrument, span, trace, Instrument};
+use tracing::{
+    debug, error, info, instrument, level_filters::LevelFilter, span, trace, Instrument, Level,
+};
 use tracing_appender::non_blocking::{self, WorkerGuard};
 use tracing_subscriber::{fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt};
-use tui_tracing::{TimingLayer, TraceStore, TracingLayer};
+use tui_tracing::{
+    Aggregation, DurationStyle, EventListState, EventListWidget, GroupBy, RetentionPolicy, Search,
+    SearchBar, SearchBarState, SpanDetailState, SpanDetailWidget, SpanFormat, SpanStatsTable,
+    SpanStatsTableState, StackWeight, TimelineState, TimelineWidget, TimingColumn, TimingLayer,
+    TraceQuery, TraceStore, TraceTreeState, TraceTreeWidget, TracingLayer,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -46,14 +54,25 @@ async fn main() -> Result<()> {
 /// Returns the internal structure that keeps track of the logs and a guard that ensures the file
 /// writer is dropped when the program exits (as the file writing is on a background thread).
 fn init_logs() -> (TraceStore, WorkerGuard) {
-    let (tui_layer, logs) = TracingLayer::new();
+    let retention = RetentionPolicy::new()
+        .with_max_events_per_span(100)
+        .with_max_age(TimeDelta::milliseconds(9900))
+        .with_warning_max_age(TimeDelta::minutes(5));
+    let logs = TraceStore::default()
+        .with_aggregation(Aggregation::new(GroupBy::Message))
+        .with_retention(retention);
+    let tui_layer = TracingLayer::with_store(logs.clone());
     let file = File::create("trace.log").unwrap();
     let (non_blocking, guard) = tracing_appender::non_blocking(file);
     let fmt_layer = tracing_subscriber::fmt::layer()
         .with_writer(non_blocking)
         .with_ansi(false);
     tracing_subscriber::registry()
-        .with(TimingLayer::default())
+        .with(
+            TimingLayer::new()
+                .with_max_intervals(256)
+                .with_long_poll_threshold(Duration::from_millis(10)),
+        )
         .with(tui_layer)
         .with(fmt_layer)
         .init();
@@ -69,13 +88,47 @@ struct App {
 #[derive(Debug, Clone)]
 struct AppData {
     logs: TraceStore,
+    ui: Arc<Mutex<UiState>>,
+    /// Notified when the UI changed without the store changing, e.g. after a key press.
+    redraw: Arc<Notify>,
     cancellation_token: CancellationToken,
 }
 
+#[derive(Debug, Default)]
+struct UiState {
+    view: View,
+    tree: TraceTreeState,
+    events: EventListState,
+    timeline: TimelineState,
+    stats: SpanStatsTableState,
+    query: TraceQuery,
+    search: SearchBarState,
+    /// Whether the details of the selected span are shown next to the tree.
+    show_detail: bool,
+    detail: SpanDetailState,
+    /// Whether the tree only shows the busy time of each span.
+    compact: bool,
+    /// Whether the tree shows the source location and thread of spans and events.
+    show_origin: bool,
+    /// Whether key presses are editing the search.
+    searching: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum View {
+    #[default]
+    Tree,
+    Events,
+    Timeline,
+    Stats,
+}
+
 impl App {
     fn new(logs: TraceStore) -> Self {
         let data = AppData {
             logs,
+            ui: Arc::default(),
+            redraw: Arc::default(),
             cancellation_token: CancellationToken::new(),
         };
         Self {
@@ -108,16 +161,24 @@ impl App {
 
     #[instrument(skip(self))]
     fn tick(&mut self) {
-        self.data.logs.remove_expired(TimeDelta::milliseconds(9900));
+        trace!(spans = self.data.logs.len(), "Tick");
     }
 
-    #[instrument(skip_all)]
+    /// Redraws whenever the store changes, and after input or a resize.
+    ///
+    /// Nothing on the render path is instrumented, as recording it would change the store and
+    /// trigger another redraw.
     async fn render_loop(mut terminal: DefaultTerminal, app_data: AppData) {
-        const FPS: f64 = 1.0;
-        let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / FPS));
-        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        const FPS: f64 = 10.0;
+        let mut changes = app_data
+            .logs
+            .subscribe()
+            .with_debounce(Duration::from_secs_f64(1.0 / FPS));
         loop {
-            interval.tick().await;
+            tokio::select! {
+                _ = changes.changed() => {}
+                _ = app_data.redraw.notified() => {}
+            }
             if let Err(err) = Self::render(&mut terminal, app_data.clone()) {
                 error!("Error rendering: {:?}", err);
                 break;
@@ -125,31 +186,69 @@ impl App {
         }
     }
 
-    #[instrument(skip(terminal, data))]
     fn render(terminal: &mut DefaultTerminal, data: AppData) -> Result<()> {
-        let start = Instant::now();
         terminal.draw(move |frame| {
-            let initial_delay = start.elapsed();
-            let area = frame.area();
-            let spans = data.logs.spans();
-            let spans_delay = start.elapsed().saturating_sub(initial_delay);
-            let text: Text = spans
-                .iter()
-                .map(ToText::to_text)
-                .flat_map(|t| t.lines)
-                .collect();
-            let scroll = (text.lines.len() as u16).saturating_sub(area.height);
-            let create_text_delay = start.elapsed().saturating_sub(spans_delay);
-            frame.render_widget(Paragraph::new(text).scroll((scroll, 0)), area);
-            let render_delay = start.elapsed().saturating_sub(create_text_delay);
-            trace!(
-                frame_count = frame.count(),
-                ?initial_delay,
-                ?spans_delay,
-                ?create_text_delay,
-                ?render_delay,
-                "Rendered"
-            );
+            let mut ui = data.ui.lock().unwrap();
+            let ui = &mut *ui;
+            let search = ui.search.search().and_then(Result::ok);
+            let show_search = ui.searching || search.is_some();
+            let [area, search_area] = Layout::vertical([
+                Constraint::Fill(1),
+                Constraint::Length(u16::from(show_search)),
+            ])
+            .areas(frame.area());
+            match ui.view {
+                View::Tree => {
+                    let selected = ui.tree.selected().filter(|_| ui.show_detail);
+                    let [tree_area, detail_area] = Layout::horizontal([
+                        Constraint::Fill(1),
+                        Constraint::Fill(u16::from(selected.is_some())),
+                    ])
+                    .areas(area);
+                    let mut format = SpanFormat::new()
+                        .with_location(ui.show_origin)
+                        .with_thread(ui.show_origin);
+                    if ui.compact {
+                        format = format
+                            .with_columns([TimingColumn::Busy, TimingColumn::BusyPercentage])
+                            .with_durations(DurationStyle::FixedWidth);
+                    }
+                    let mut tree = TraceTreeWidget::new(&data.logs)
+                        .query(&ui.query)
+                        .format(&format);
+                    if let Some(search) = &search {
+                        tree = tree.search(search);
+                    }
+                    frame.render_stateful_widget(tree, tree_area, &mut ui.tree);
+                    if let Some(selected) = selected {
+                        let detail = SpanDetailWidget::new(&data.logs, selected.span())
+                            .block(Block::bordered().title("Span"));
+                        frame.render_stateful_widget(detail, detail_area, &mut ui.detail);
+                    }
+                }
+                View::Events => {
+                    let mut events = EventListWidget::new(&data.logs).query(&ui.query);
+                    if let Some(search) = &search {
+                        events = events.search(search);
+                    }
+                    frame.render_stateful_widget(events, area, &mut ui.events);
+                }
+                View::Timeline => frame.render_stateful_widget(
+                    TimelineWidget::new(&data.logs),
+                    area,
+                    &mut ui.timeline,
+                ),
+                View::Stats => frame.render_stateful_widget(
+                    SpanStatsTable::new(&data.logs),
+                    area,
+                    &mut ui.stats,
+                ),
+            }
+            let mut search_bar = SearchBar::new();
+            if ui.view == View::Tree {
+                search_bar = search_bar.matches(ui.tree.selected_match(), ui.tree.match_count());
+            }
+            frame.render_stateful_widget(search_bar, search_area, &mut ui.search);
         })?;
         Ok(())
     }
@@ -160,11 +259,12 @@ impl App {
             match event {
                 Ok(event) => {
                     debug!(?event, "Event");
-                    if let Event::Key(event) = event {
-                        if event.code == KeyCode::Char('q') {
-                            self.data.cancellation_token.cancel();
-                        }
+                    match event {
+                        Event::Key(event) => self.handle_key(event),
+                        Event::Resize(..) => {}
+                        _ => return Ok(()),
                     }
+                    self.data.redraw.notify_one();
                 }
                 Err(e) => {
                     error!("Error: {:?}", e);
@@ -173,4 +273,149 @@ impl App {
         }
         Ok(())
     }
+
+    fn handle_key(&mut self, event: KeyEvent) {
+        let mut ui = self.data.ui.lock().unwrap();
+        if ui.searching {
+            Self::handle_search_key(&mut ui, event);
+            return;
+        }
+        match event.code {
+            KeyCode::Char('q') => self.data.cancellation_token.cancel(),
+            KeyCode::Tab => {
+                ui.view = match ui.view {
+                    View::Tree => View::Events,
+                    View::Events => View::Timeline,
+                    View::Timeline => View::Stats,
+                    View::Stats => View::Tree,
+                }
+            }
+            KeyCode::Char('l') => {
+                // cycle through the minimum levels, from most to least verbose
+                let level = match ui.query.min_level().into_level() {
+                    Some(Level::TRACE) => LevelFilter::DEBUG,
+                    Some(Level::DEBUG) => LevelFilter::INFO,
+                    Some(Level::INFO) => LevelFilter::WARN,
+                    Some(Level::WARN) => LevelFilter::ERROR,
+                    _ => LevelFilter::TRACE,
+                };
+                ui.query = ui.query.clone().with_min_level(level);
+            }
+            KeyCode::Char('/') => {
+                ui.search.clear();
+                ui.searching = true;
+            }
+            KeyCode::Char('d') => {
+                ui.show_detail = !ui.show_detail;
+                ui.detail.scroll_to_top();
+            }
+            KeyCode::Char('c') => ui.compact = !ui.compact,
+            KeyCode::Char('o') => ui.show_origin = !ui.show_origin,
+            KeyCode::Char('e') if ui.view == View::Tree => {
+                let result = File::create("trace.json")
+                    .and_then(|file| self.data.logs.write_chrome_trace(BufWriter::new(file)));
+                match result {
+                    Ok(()) => info!("exported the trace to trace.json"),
+                    Err(error) => error!(%error, "failed to export the trace"),
+                }
+            }
+            KeyCode::Char('F') if ui.view == View::Tree => {
+                let result = File::create("stacks.folded").and_then(|file| {
+                    let writer = BufWriter::new(file);
+                    self.data
+                        .logs
+                        .write_folded_stacks(writer, StackWeight::SelfBusy)
+                });
+                match result {
+                    Ok(()) => info!("exported the folded stacks to stacks.folded"),
+                    Err(error) => error!(%error, "failed to export the folded stacks"),
+                }
+            }
+            KeyCode::Char('[') => ui.detail.page_up(),
+            KeyCode::Char(']') => ui.detail.page_down(),
+            _ => match ui.view {
+                View::Tree => Self::handle_tree_key(&mut ui.tree, event),
+                View::Events => Self::handle_events_key(&mut ui.events, event),
+                View::Timeline => Self::handle_timeline_key(&mut ui.timeline, event),
+                View::Stats => Self::handle_stats_key(&mut ui.stats, event),
+            },
+        }
+    }
+
+    fn handle_tree_key(tree: &mut TraceTreeState, event: KeyEvent) {
+        match event.code {
+            KeyCode::Char('j') | KeyCode::Down => tree.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => tree.select_previous(),
+            KeyCode::PageDown => tree.page_down(),
+            KeyCode::PageUp => tree.page_up(),
+            KeyCode::Char('g') | KeyCode::Home => tree.select_first(),
+            KeyCode::Char('G') | KeyCode::End => tree.select_last(),
+            KeyCode::Enter | KeyCode::Char(' ') => tree.toggle_selected(),
+            KeyCode::Char('f') => {
+                let follow = !tree.is_following();
+                tree.set_follow(follow);
+            }
+            KeyCode::Char('n') => tree.select_next_match(),
+            KeyCode::Char('N') => tree.select_previous_match(),
+            _ => {}
+        }
+    }
+
+    fn handle_search_key(ui: &mut UiState, event: KeyEvent) {
+        match event.code {
+            KeyCode::Esc => {
+                ui.search.clear();
+                ui.searching = false;
+            }
+            KeyCode::Enter => {
+                ui.searching = false;
+                ui.tree.select_next_match();
+            }
+            KeyCode::Backspace => ui.search.pop(),
+            KeyCode::Char('r') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                ui.search.toggle_regex()
+            }
+            KeyCode::Char(c) => ui.search.push(c),
+            _ => {}
+        }
+    }
+
+    fn handle_timeline_key(timeline: &mut TimelineState, event: KeyEvent) {
+        match event.code {
+            KeyCode::Char('j') | KeyCode::Down => timeline.scroll_down(1),
+            KeyCode::Char('k') | KeyCode::Up => timeline.scroll_up(1),
+            KeyCode::Char('+') | KeyCode::Char('=') => timeline.zoom_in(),
+            KeyCode::Char('-') => timeline.zoom_out(),
+            KeyCode::Left => timeline.pan_left(),
+            KeyCode::Right => timeline.pan_right(),
+            KeyCode::Char('0') => timeline.reset(),
+            _ => {}
+        }
+    }
+
+    fn handle_stats_key(stats: &mut SpanStatsTableState, event: KeyEvent) {
+        match event.code {
+            KeyCode::Char('j') | KeyCode::Down => stats.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => stats.select_previous(),
+            KeyCode::Right => stats.sort_by_next_column(),
+            KeyCode::Left => stats.sort_by_previous_column(),
+            KeyCode::Char('r') => {
+                let column = stats.sort_column();
+                stats.sort_by(column);
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_events_key(events: &mut EventListState, event: KeyEvent) {
+        match event.code {
+            KeyCode::Char('j') | KeyCode::Down => events.scroll_down(1),
+            KeyCode::Char('k') | KeyCode::Up => events.scroll_up(1),
+            KeyCode::PageDown => events.page_down(),
+            KeyCode::PageUp => events.page_up(),
+            KeyCode::Char('g') | KeyCode::Home => events.scroll_to_top(),
+            KeyCode::Char('G') | KeyCode::End => events.scroll_to_bottom(),
+            _ => {}
+        }
+    }
 }
@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Local};
+use tracing::callsite::Identifier;
+
+use crate::storage::{EventId, EventRecord, Level};
+
+/// Configuration for grouping repeated events together.
+///
+/// Each group keeps a count, the time of the first and last occurrence, and a bounded sample of
+/// the most recent events so that the individual occurrences can still be drilled into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Aggregation {
+    group_by: GroupBy,
+    max_samples: usize,
+}
+
+/// How events are grouped when [`Aggregation`] is enabled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    /// Events from the same callsite with the same message are grouped together.
+    #[default]
+    Message,
+    /// Events from the same callsite are grouped together regardless of their message.
+    ///
+    /// This collapses events whose message is formatted from changing values (e.g.
+    /// `info!("received {n} bytes")`) into a single group per field template.
+    Callsite,
+}
+
+impl Default for Aggregation {
+    fn default() -> Self {
+        Self::new(GroupBy::default())
+    }
+}
+
+impl Aggregation {
+    /// The default number of sample events kept for each group.
+    pub const DEFAULT_MAX_SAMPLES: usize = 10;
+
+    /// Create a new aggregation configuration.
+    pub const fn new(group_by: GroupBy) -> Self {
+        Self {
+            group_by,
+            max_samples: Self::DEFAULT_MAX_SAMPLES,
+        }
+    }
+
+    /// Set the number of most recent events kept as samples for each group.
+    pub const fn with_max_samples(mut self, max_samples: usize) -> Self {
+        self.max_samples = max_samples;
+        self
+    }
+
+    /// Get the grouping strategy.
+    pub fn group_by(&self) -> GroupBy {
+        self.group_by
+    }
+
+    /// Get the number of sample events kept for each group.
+    pub fn max_samples(&self) -> usize {
+        self.max_samples
+    }
+
+    /// Add an event to the matching group, creating a new group if there is none.
+    pub(crate) fn insert(&self, groups: &mut Vec<EventGroup>, event: EventRecord) {
+        let key = self.key(&event);
+        match groups.iter_mut().rev().find(|group| group.key == key) {
+            Some(group) => group.push(event, self.max_samples),
+            None => groups.push(EventGroup::new(key, event, self.max_samples)),
+        }
+    }
+
+    fn key(&self, event: &EventRecord) -> GroupKey {
+        let message = match self.group_by {
+            GroupBy::Message => event.message().map(str::to_owned),
+            GroupBy::Callsite => None,
+        };
+        GroupKey {
+            callsite: event.callsite.clone(),
+            message,
+        }
+    }
+}
+
+/// The identity of an [`EventGroup`], which stays the same as the groups around it are evicted.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct GroupKey {
+    callsite: Identifier,
+    message: Option<String>,
+}
+
+/// A group of repeated events.
+#[derive(Debug, Clone)]
+pub struct EventGroup {
+    key: GroupKey,
+    /// The id of the first event in the group.
+    id: EventId,
+    /// The level of the events in the group.
+    pub level: Level,
+    /// The target of the events in the group.
+    pub target: String,
+    /// The number of events in the group, including those no longer kept as samples.
+    pub count: u64,
+    /// The time of the first event in the group.
+    pub first_time: DateTime<Local>,
+    /// The time of the most recent event in the group.
+    pub last_time: DateTime<Local>,
+    /// The most recent events in the group, oldest first.
+    pub samples: VecDeque<EventRecord>,
+    message: Option<String>,
+}
+
+impl EventGroup {
+    fn new(key: GroupKey, event: EventRecord, max_samples: usize) -> Self {
+        let mut group = Self {
+            key,
+            id: event.id,
+            level: event.level.clone(),
+            target: event.target.clone(),
+            count: 0,
+            first_time: event.time,
+            last_time: event.time,
+            samples: VecDeque::new(),
+            message: None,
+        };
+        group.push(event, max_samples);
+        group
+    }
+
+    fn push(&mut self, event: EventRecord, max_samples: usize) {
+        self.count += 1;
+        self.last_time = event.time;
+        self.message = event.message().map(str::to_owned);
+        self.samples.push_back(event);
+        while self.samples.len() > max_samples {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Returns the identity of the group.
+    pub(crate) fn key(&self) -> &GroupKey {
+        &self.key
+    }
+
+    /// Returns the id of the first event in the group, which identifies the group in its store.
+    pub fn id(&self) -> EventId {
+        self.id
+    }
+
+    /// Returns the message of the most recent event in the group.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
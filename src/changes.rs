@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+/// A subscription to changes in a [`TraceStore`](crate::TraceStore).
+///
+/// Created by [`TraceStore::subscribe`](crate::TraceStore::subscribe). A render loop can await
+/// [`changed`](Self::changed) to redraw only when new spans or events have been recorded, rather
+/// than on a fixed interval.
+#[derive(Debug, Clone)]
+pub struct ChangeSubscription {
+    receiver: watch::Receiver<u64>,
+    debounce: Duration,
+}
+
+impl ChangeSubscription {
+    pub(crate) fn new(receiver: watch::Receiver<u64>) -> Self {
+        Self {
+            receiver,
+            debounce: Duration::ZERO,
+        }
+    }
+
+    /// Wait for this long after a change before waking, so that bursts of changes only wake the
+    /// subscriber once.
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Waits until the store has changed since the last call, returning the new generation.
+    ///
+    /// Changes that happen during the debounce period are included in the returned generation and
+    /// do not cause the next call to return immediately.
+    pub async fn changed(&mut self) -> u64 {
+        if self.receiver.changed().await.is_err() {
+            // the store has been dropped, so nothing will ever change again
+            std::future::pending::<()>().await;
+        }
+        if !self.debounce.is_zero() {
+            tokio::time::sleep(self.debounce).await;
+        }
+        *self.receiver.borrow_and_update()
+    }
+
+    /// Get the generation of the store as of the last call to [`changed`](Self::changed).
+    pub fn generation(&self) -> u64 {
+        *self.receiver.borrow()
+    }
+}
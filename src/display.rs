@@ -1,49 +1,354 @@
-use std::iter;
+use std::{iter, ops::Range, time::Duration};
 
+use chrono::{DateTime, Local};
 use itertools::{Itertools, Position};
+use quanta::Instant;
 use ratatui::{
     style::{Color, Modifier},
-    text::{Line, Text, ToLine, ToSpan, ToText},
+    text::{Line, Span, Text, ToLine, ToSpan, ToText},
 };
 use ratatui_macros::{line, span};
 
-use crate::storage::{EventRecord, Level, SpanRecord};
+use crate::{
+    aggregation::EventGroup,
+    storage::{EventRecord, FieldValue, Level, Location, SpanRecord, ThreadInfo},
+    timing_layer::Timing,
+};
+
+/// How a [`SpanRecord`] is formatted as a single line.
+///
+/// By default the line shows the absolute start time, the level, target, name and fields of the
+/// span, followed by every [`TimingColumn`] with durations in human units. The source location
+/// and thread of spans and events can be shown as well. The `ToLine` implementations of
+/// [`SpanRecord`] and [`EventRecord`] use the default format; widgets that show spans accept a
+/// format to show more or less detail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanFormat {
+    columns: Vec<TimingColumn>,
+    durations: DurationStyle,
+    percentage_precision: usize,
+    start_time: StartTime,
+    location: bool,
+    thread: bool,
+}
+
+/// A timing value shown by a [`SpanFormat`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimingColumn {
+    /// The time the span spent entered (busy).
+    Busy,
+    /// The busy time as a percentage of the total time.
+    BusyPercentage,
+    /// The time the span was entered, excluding the time spent in entered child spans.
+    SelfBusy,
+    /// The time the span was open but not entered.
+    Idle,
+    /// The time since the span was created, until it closed.
+    Total,
+}
+
+/// How a [`SpanFormat`] shows durations.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DurationStyle {
+    /// In the most readable unit, e.g. `1.23ms` or `4.56s`.
+    #[default]
+    Human,
+    /// In milliseconds, padded to the same width so that values line up across lines.
+    FixedWidth,
+}
+
+/// How a [`SpanFormat`] shows the start time of a span.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StartTime {
+    /// The local time of day.
+    #[default]
+    Absolute,
+    /// The time since the given origin, e.g. the start of the trace.
+    Relative(DateTime<Local>),
+}
+
+impl TimingColumn {
+    /// All of the columns, in the default order.
+    pub const ALL: [TimingColumn; 5] = [
+        TimingColumn::Busy,
+        TimingColumn::BusyPercentage,
+        TimingColumn::SelfBusy,
+        TimingColumn::Idle,
+        TimingColumn::Total,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            TimingColumn::Busy | TimingColumn::BusyPercentage => "Busy",
+            TimingColumn::SelfBusy => "Self",
+            TimingColumn::Idle => "Idle",
+            TimingColumn::Total => "Total",
+        }
+    }
+}
+
+impl Default for SpanFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpanFormat {
+    /// Create the default format.
+    pub fn new() -> Self {
+        Self {
+            columns: TimingColumn::ALL.to_vec(),
+            durations: DurationStyle::Human,
+            percentage_precision: 1,
+            start_time: StartTime::Absolute,
+            location: false,
+            thread: false,
+        }
+    }
+
+    /// Set the timing columns to show, in order. An empty list hides the timing entirely.
+    pub fn with_columns(mut self, columns: impl IntoIterator<Item = TimingColumn>) -> Self {
+        self.columns = columns.into_iter().collect();
+        self
+    }
+
+    /// Set how durations are shown.
+    pub fn with_durations(mut self, style: DurationStyle) -> Self {
+        self.durations = style;
+        self
+    }
+
+    /// Set the number of decimal places of percentages.
+    pub fn with_percentage_precision(mut self, precision: usize) -> Self {
+        self.percentage_precision = precision;
+        self
+    }
+
+    /// Set how the start time is shown.
+    pub fn with_start_time(mut self, start_time: StartTime) -> Self {
+        self.start_time = start_time;
+        self
+    }
+
+    /// Show the source location of spans and events, e.g. `src/db.rs:42`.
+    pub fn with_location(mut self, location: bool) -> Self {
+        self.location = location;
+        self
+    }
+
+    /// Show the thread that spans were created on and events were recorded on, along with the
+    /// tokio task when the `tokio` feature records it.
+    pub fn with_thread(mut self, thread: bool) -> Self {
+        self.thread = thread;
+        self
+    }
+
+    /// Get the timing columns to show.
+    pub fn columns(&self) -> &[TimingColumn] {
+        &self.columns
+    }
+
+    /// Get how durations are shown.
+    pub fn durations(&self) -> DurationStyle {
+        self.durations
+    }
+
+    /// Get the number of decimal places of percentages.
+    pub fn percentage_precision(&self) -> usize {
+        self.percentage_precision
+    }
+
+    /// Get how the start time is shown.
+    pub fn start_time(&self) -> StartTime {
+        self.start_time
+    }
+
+    /// Returns whether the source location of spans and events is shown.
+    pub fn shows_location(&self) -> bool {
+        self.location
+    }
+
+    /// Returns whether the thread of spans and events is shown.
+    pub fn shows_thread(&self) -> bool {
+        self.thread
+    }
+
+    /// Format the span as a line.
+    pub fn format<'a>(&self, span: &'a SpanRecord) -> Line<'a> {
+        self.format_with_searched(span).0
+    }
+
+    /// Formats the span as a line, along with the range of its spans that show the target, name
+    /// and fields, which are the parts a [`Search`](crate::Search) matches.
+    pub(crate) fn format_with_searched<'a>(
+        &self,
+        span: &'a SpanRecord,
+    ) -> (Line<'a>, Range<usize>) {
+        let fields = span
+            .fields
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .join(" ");
+        let start_time = match self.start_time {
+            StartTime::Absolute => span.start_time.format("%H:%M:%S").to_string(),
+            StartTime::Relative(origin) => match (span.start_time - origin).to_std() {
+                Ok(elapsed) => format!("+{elapsed:.3?}"),
+                Err(_) => format!(
+                    "-{:.3?}",
+                    (origin - span.start_time).to_std().unwrap_or_default()
+                ),
+            },
+        };
+        let mut line = line![
+            span!(Modifier::DIM; "{start_time} "),
+            span.level.to_span(),
+            span!(" "),
+            span!(Modifier::DIM; "{}::{}", span.target, span.name),
+        ];
+        if !fields.is_empty() {
+            line.push_span(span!(Modifier::DIM | Modifier::ITALIC; "{{{fields}}}"));
+        }
+        let searched = 3..line.spans.len();
+        line.extend(self.origin_spans(&span.location, span.thread.as_ref()));
+        // include the time in the current state, so that spans stuck in a poll show it
+        line.extend(self.timing_spans(&span.timing, Instant::now()));
+        (line, searched)
+    }
+
+    /// Format the event as a line.
+    pub fn format_event<'a>(&self, event: &'a EventRecord) -> Line<'a> {
+        self.format_event_with_searched(event).0
+    }
+
+    /// Formats the event as a line, along with the range of its spans that show the message and
+    /// fields, which are the parts a [`Search`](crate::Search) matches.
+    pub(crate) fn format_event_with_searched<'a>(
+        &self,
+        event: &'a EventRecord,
+    ) -> (Line<'a>, Range<usize>) {
+        let message = event.message().unwrap_or_default();
+        let fields = event
+            .fields
+            .iter()
+            .filter(|(k, _)| *k != "message")
+            .map(|(k, v)| format!("{}: {}", k, v))
+            .join(", ");
+        let mut line = line![
+            span!(Modifier::DIM; "{}", event.time.format("%H:%M:%S")),
+            " ",
+            event.level.to_span(),
+            span!(" {message}")
+        ];
+        if !fields.is_empty() {
+            line.push_span(span!(Modifier::DIM | Modifier::ITALIC; " {fields}"));
+        }
+        let searched = 3..line.spans.len();
+        line.extend(self.origin_spans(&event.location, Some(&event.thread)));
+        (line, searched)
+    }
+
+    /// Formats the source location and thread, if they are shown.
+    fn origin_spans(&self, location: &Location, thread: Option<&ThreadInfo>) -> Vec<Span<'static>> {
+        let mut spans = Vec::new();
+        if self.location && !location.is_unknown() {
+            spans.push(span!(Modifier::DIM; " at {location}"));
+        }
+        if let Some(thread) = thread.filter(|_| self.thread) {
+            spans.push(span!(Modifier::DIM; " on {thread}"));
+            if let Some(task) = thread.task {
+                spans.push(span!(Modifier::DIM; " in task {task}"));
+            }
+        }
+        spans
+    }
+
+    /// Formats the timing columns and warnings about long polls.
+    fn timing_spans(&self, timing: &Timing, now: Instant) -> Vec<Span<'static>> {
+        let mut spans = Vec::new();
+        let mut previous = None;
+        for &column in &self.columns {
+            let separator = if previous.is_none() { " [" } else { ", " };
+            let value = match column {
+                TimingColumn::Busy => self.duration(timing.busy_duration_at(now)),
+                TimingColumn::BusyPercentage => {
+                    let percentage =
+                        percentage(timing.busy_duration_at(now), timing.total_duration_at(now));
+                    format!("{percentage:.*}%", self.percentage_precision)
+                }
+                TimingColumn::SelfBusy => self.duration(timing.self_busy_duration_at(now)),
+                TimingColumn::Idle => self.duration(timing.idle_duration_at(now)),
+                TimingColumn::Total => self.duration(timing.total_duration_at(now)),
+            };
+            match (column, previous) {
+                // shown as "Busy: 1.23ms (45.6%)"
+                (TimingColumn::BusyPercentage, Some(TimingColumn::Busy)) => spans.extend([
+                    span!(Modifier::DIM; " ("),
+                    span!(Modifier::DIM | Modifier::BOLD; "{value}"),
+                    span!(Modifier::DIM; ")"),
+                ]),
+                (TimingColumn::BusyPercentage, _) => spans.extend([
+                    span!(Modifier::DIM; "{separator}Busy: "),
+                    span!(Modifier::DIM | Modifier::BOLD; "{value}"),
+                ]),
+                _ => spans.extend([
+                    span!(Modifier::DIM; "{separator}{}:", column.label()),
+                    span!(Modifier::DIM | Modifier::BOLD; "{value}"),
+                ]),
+            }
+            previous = Some(column);
+        }
+        if previous.is_some() {
+            spans.push(span!(Modifier::DIM; "]"));
+        }
+        if timing.is_stuck_at(now) {
+            let poll = timing.current_poll_at(now).unwrap_or_default();
+            spans.push(span!(Color::Red; " ⚠ busy for {}", self.duration(poll).trim_start()));
+        }
+        if timing.long_poll_count() > 0 {
+            spans.push(span!(
+                Color::Yellow;
+                " ⚠ {} long polls (max {})",
+                timing.long_poll_count(),
+                self.duration(timing.longest_poll()).trim_start()
+            ));
+        }
+        spans
+    }
+
+    /// Formats a duration, including a leading space.
+    fn duration(&self, duration: Duration) -> String {
+        match self.durations {
+            DurationStyle::Human => format!(" {duration:.2?}"),
+            DurationStyle::FixedWidth => format!("{:>10.3}ms", duration.as_secs_f64() * 1e3),
+        }
+    }
+}
+
+/// Returns `part` as a percentage of `total`, or 0 if `total` is zero.
+pub(crate) fn percentage(part: Duration, total: Duration) -> f64 {
+    if total.is_zero() {
+        0.0
+    } else {
+        part.as_secs_f64() / total.as_secs_f64() * 100.0
+    }
+}
 
 impl ToLine for SpanRecord {
     fn to_line(&self) -> Line {
-        let timing = self.timing;
-        let busy_percentage = timing
-            .busy_duration()
-            .as_nanos()
-            .checked_div(timing.total_duration().as_nanos())
-            .unwrap_or_default();
-        line![
-            span!(Modifier::DIM; "{} ", self.start_time.format("%H:%M:%S")),
-            self.level.to_span(),
-            span!(" "),
-            span!(Modifier::DIM; "{}::{}", self.target, self.name),
-            span!(Modifier::DIM; " [Busy:"),
-            span!(Modifier::DIM | Modifier::BOLD; "{:>8.2?}", timing.busy_duration()),
-            span!(Modifier::DIM; "("),
-            span!(Modifier::DIM | Modifier::BOLD; "{busy_percentage:.2}"),
-            span!(Modifier::DIM; "%), Idle:"),
-            span!(Modifier::DIM | Modifier::BOLD; "{:>8.2?}",  timing.idle_duration()),
-            span!(Modifier::DIM; ", Total:"),
-            span!(Modifier::DIM | Modifier::BOLD; "{:>8.2?}", timing.total_duration()),
-        ]
+        SpanFormat::default().format(self)
     }
 }
 
 impl ToText for SpanRecord {
     fn to_text(&self) -> Text {
         let span_line = self.to_line();
-        let event_lines = self
-            .events
-            .iter()
-            .rev()
-            .take(4) // todo: make this configurable and based on some sort of retention policy instead of a fixed number
-            .rev()
-            .map(ToLine::to_line)
+        let event_lines: Vec<Line> = if self.event_groups.is_empty() {
+            self.events.iter().map(ToLine::to_line).collect()
+        } else {
+            self.event_groups.iter().map(ToLine::to_line).collect()
+        };
+        let event_lines = event_lines
+            .into_iter()
             .with_position()
             .map(|(pos, mut line)| {
                 let symbol = if matches!(pos, Position::Last | Position::Only) {
@@ -60,23 +365,76 @@ impl ToText for SpanRecord {
 
 impl ToLine for EventRecord {
     fn to_line(&self) -> Line {
-        let message = self.fields["message"].clone();
-        let fields = self
-            .fields
+        SpanFormat::default().format_event(self)
+    }
+}
+
+impl ToLine for EventGroup {
+    fn to_line(&self) -> Line {
+        group_line_with_searched(self).0
+    }
+}
+
+/// Formats the group as a line, along with the range of its spans that show the message, which
+/// is the part a [`Search`](crate::Search) matches.
+pub(crate) fn group_line_with_searched(group: &EventGroup) -> (Line<'_>, Range<usize>) {
+    let mut line = line![
+        span!(Modifier::DIM; "{}", group.first_time.format("%H:%M:%S")),
+        " ",
+        group.level.to_span(),
+        span!(" {}", group.message().unwrap_or_default()),
+    ];
+    if group.count > 1 {
+        line.push_span(span!(Modifier::BOLD; " ×{}", group.count));
+        line.push_span(span!(Modifier::DIM; " (last {})", group.last_time.format("%H:%M:%S")));
+    }
+    (line, 3..4)
+}
+
+impl ToText for EventGroup {
+    /// Renders the group followed by each of its sampled events.
+    fn to_text(&self) -> Text {
+        let sample_lines = self
+            .samples
             .iter()
-            .filter(|(k, _)| *k != "message")
-            .map(|(k, v)| format!("{}: {}", k, v))
-            .join(", ");
-        let mut line = line![
-            span!(Modifier::DIM; "{}", self.time.format("%H:%M:%S")),
-            " ",
-            self.level.to_span(),
-            span!(" {message}")
-        ];
-        if !fields.is_empty() {
-            line.push_span(span!(Modifier::DIM | Modifier::ITALIC; " {fields}"));
+            .map(ToLine::to_line)
+            .with_position()
+            .map(|(pos, mut line)| {
+                let symbol = if matches!(pos, Position::Last | Position::Only) {
+                    "   └─ "
+                } else {
+                    "   ├─ "
+                };
+                line.spans.insert(0, symbol.into());
+                line
+            });
+        Text::from_iter(iter::once(self.to_line()).chain(sample_lines))
+    }
+}
+
+impl FieldValue {
+    /// Renders the value, followed by the sources of an error as a numbered "Caused by" list.
+    pub(crate) fn to_styled_text(&self) -> Text<'_> {
+        let color = match self {
+            FieldValue::Bool(_) | FieldValue::I64(_) | FieldValue::U64(_) | FieldValue::F64(_) => {
+                Color::Cyan
+            }
+            FieldValue::Error { .. } => Color::Red,
+            FieldValue::Str(_) | FieldValue::Debug(_) => Color::Reset,
+        };
+        let mut text = Text::from(span!(color; "{self}"));
+        if let FieldValue::Error { sources, .. } = self {
+            if !sources.is_empty() {
+                text.push_line(span!(Modifier::DIM; "Caused by:"));
+            }
+            for (index, source) in sources.iter().enumerate() {
+                text.push_line(line![
+                    span!(Modifier::DIM; "{index:>4}: "),
+                    span!(Color::Red; "{source}"),
+                ]);
+            }
         }
-        line
+        text
     }
 }
 
@@ -87,7 +445,7 @@ impl ToSpan for Level {
 }
 
 impl Level {
-    fn color(&self) -> Color {
+    pub(crate) fn color(&self) -> Color {
         match self.0 {
             tracing::Level::TRACE => Color::Magenta,
             tracing::Level::DEBUG => Color::Blue,
@@ -97,3 +455,39 @@ impl Level {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use quanta::Clock;
+
+    use super::*;
+
+    #[test]
+    fn timing_columns() {
+        let (clock, mock) = Clock::mock();
+        quanta::with_clock(&clock, || {
+            let mut timing = Timing::new();
+            mock.increment(Duration::from_millis(3));
+            timing.enter();
+            mock.increment(Duration::from_millis(1));
+            timing.exit();
+            let text = |format: &SpanFormat| {
+                format
+                    .timing_spans(&timing, Instant::now())
+                    .iter()
+                    .map(|span| span.content.as_ref())
+                    .collect::<String>()
+            };
+            assert_eq!(
+                text(&SpanFormat::new()),
+                " [Busy: 1.00ms (25.0%), Self: 1.00ms, Idle: 3.00ms, Total: 4.00ms]"
+            );
+            let format = SpanFormat::new()
+                .with_columns([TimingColumn::BusyPercentage, TimingColumn::Total])
+                .with_durations(DurationStyle::FixedWidth)
+                .with_percentage_precision(0);
+            assert_eq!(text(&format), " [Busy: 25%, Total:     4.000ms]");
+            assert_eq!(text(&SpanFormat::new().with_columns([])), "");
+        });
+    }
+}
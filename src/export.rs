@@ -0,0 +1,409 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    thread::ThreadId,
+};
+
+use chrono::{DateTime, Local};
+use indexmap::IndexMap;
+use itertools::Itertools;
+use quanta::Instant;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    storage::{EventRecord, FieldMap, FieldValue, SpanKey, SpanRecord, Spans},
+    IntervalLog,
+};
+
+/// Writes the spans and events in the Chrome Trace Event format.
+///
+/// The lifetime of each span is written as an async ("b" and "e") pair, as the lifetimes of spans
+/// created on one thread do not necessarily nest. When the [`TimingLayer`](crate::TimingLayer)
+/// records busy intervals, each interval is also written as a complete ("X") event on the track of
+/// the thread that entered the span, so that nested spans are drawn under their parent. Intervals
+/// dropped from a full [`IntervalLog`](crate::IntervalLog) are summarized in the
+/// `dropped_intervals` arg of the "b" event. Events are written as instant ("i") events with their
+/// fields as args. Times are in microseconds since the first span started.
+pub(crate) fn write_chrome_trace(spans: &Spans, mut writer: impl Write) -> io::Result<()> {
+    let origin = spans
+        .iter()
+        .map(|(_, span)| span.start_time)
+        .min()
+        .unwrap_or_else(Local::now);
+    // intervals only record the id of the thread, so the names are taken from the spans and
+    // events recorded on each thread
+    let names = spans
+        .iter()
+        .flat_map(|(_, span)| {
+            let events = span.event_groups.iter().flat_map(|group| &group.samples);
+            let events = span.events.iter().chain(events).map(EventRecord::thread);
+            span.thread.iter().chain(events)
+        })
+        .map(|thread| (thread.id, thread.to_string()))
+        .collect();
+    let mut trace = ChromeTrace {
+        origin,
+        now: Local::now(),
+        instant: Instant::now(),
+        names,
+        threads: HashMap::new(),
+        events: Vec::new(),
+    };
+    for (key, span) in spans.user_spans() {
+        trace.push_span(key, span);
+    }
+    for (_, span) in spans.iter() {
+        let samples = span.event_groups.iter().flat_map(|group| &group.samples);
+        for event in span.events.iter().chain(samples) {
+            trace.push_event(event);
+        }
+    }
+    let trace = json!({
+        "traceEvents": trace.events,
+        "displayTimeUnit": "ms",
+    });
+    serde_json::to_writer(&mut writer, &trace)?;
+    writer.flush()
+}
+
+/// How each stack is weighted when writing folded stacks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StackWeight {
+    /// The busy time of the span excluding its entered child spans, so that each frame of a flame
+    /// graph is exactly as wide as the span's busy time.
+    #[default]
+    SelfBusy,
+    /// The busy time of the span, including the time spent in its entered child spans.
+    ///
+    /// Flame graph tools add the weights of child stacks to their parents, so parents are drawn
+    /// wider than their busy time.
+    Busy,
+}
+
+/// Writes the spans as folded stacks, one line per distinct stack of span names, e.g.
+/// `request;db_query 1234`.
+///
+/// Spans with the same stack are combined by adding their weights, in microseconds. Stacks with
+/// a weight of zero are left out.
+pub(crate) fn write_folded_stacks(
+    spans: &Spans,
+    mut writer: impl Write,
+    weight: StackWeight,
+) -> io::Result<()> {
+    let now = Instant::now();
+    let mut stacks: IndexMap<String, u128> = IndexMap::new();
+    for (key, span) in spans.user_spans() {
+        let duration = match weight {
+            StackWeight::Busy => span.timing.busy_duration_at(now),
+            StackWeight::SelfBusy => span.timing.self_busy_duration_at(now),
+        };
+        // `;` separates frames
+        let stack = spans
+            .path(key)
+            .iter()
+            .map(|span| span.name.replace(';', ","))
+            .join(";");
+        *stacks.entry(stack).or_default() += duration.as_micros();
+    }
+    for (stack, micros) in stacks {
+        if micros > 0 {
+            writeln!(writer, "{stack} {micros}")?;
+        }
+    }
+    writer.flush()
+}
+
+struct ChromeTrace {
+    origin: DateTime<Local>,
+    now: DateTime<Local>,
+    instant: Instant,
+    /// The name of each thread.
+    names: HashMap<ThreadId, String>,
+    /// The id of each thread's track, in the order the threads were first seen.
+    threads: HashMap<ThreadId, u64>,
+    events: Vec<Value>,
+}
+
+impl ChromeTrace {
+    fn push_span(&mut self, key: SpanKey, span: &SpanRecord) {
+        let start = self.micros(span.start_time);
+        let end = self.micros(span.close_time.unwrap_or(self.now));
+        // include the current busy interval of spans that are still open
+        let timing = span.timing.snapshot(self.instant);
+        let intervals = timing.intervals();
+        let mut begin_args = args(&span.fields);
+        if let Some(dropped) = intervals.map(IntervalLog::dropped) {
+            if dropped.count > 0 {
+                let dropped = json!({
+                    "count": dropped.count,
+                    "busy_us": dropped.busy.as_secs_f64() * 1e6,
+                    "end_us": dropped.end.as_secs_f64() * 1e6,
+                });
+                begin_args.insert("dropped_intervals".to_owned(), dropped);
+            }
+        }
+        let tid = span
+            .thread
+            .as_ref()
+            .map_or(0, |thread| self.thread_id(thread.id));
+        for (phase, ts, args) in [("b", start, begin_args), ("e", end, Map::new())] {
+            self.events.push(json!({
+                "name": span.name,
+                "cat": span.target,
+                "ph": phase,
+                "id": key.into_u64(),
+                "ts": ts,
+                "pid": 1,
+                "tid": tid,
+                "args": args,
+            }));
+        }
+        for interval in intervals.into_iter().flat_map(IntervalLog::iter) {
+            let tid = self.thread_id(interval.thread);
+            let duration = interval.end.saturating_sub(interval.start);
+            self.events.push(json!({
+                "name": span.name,
+                "cat": span.target,
+                "ph": "X",
+                "ts": start + interval.start.as_secs_f64() * 1e6,
+                "dur": duration.as_secs_f64() * 1e6,
+                "pid": 1,
+                "tid": tid,
+                "args": args(&span.fields),
+            }));
+        }
+    }
+
+    fn push_event(&mut self, event: &EventRecord) {
+        let tid = self.thread_id(event.thread().id);
+        self.events.push(json!({
+            "name": event.message().unwrap_or(event.target()),
+            "cat": event.target(),
+            "ph": "i",
+            "s": "t",
+            "ts": self.micros(event.time()),
+            "pid": 1,
+            "tid": tid,
+            "args": args(event.fields()),
+        }));
+    }
+
+    /// Returns the id of the thread's track, naming the track the first time the thread is seen.
+    fn thread_id(&mut self, id: ThreadId) -> u64 {
+        let next = self.threads.len() as u64 + 1;
+        let tid = *self.threads.entry(id).or_insert(next);
+        if tid == next {
+            let name = self
+                .names
+                .get(&id)
+                .cloned()
+                .unwrap_or_else(|| format!("{id:?}"));
+            self.events.push(json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": 1,
+                "tid": tid,
+                "args": { "name": name },
+            }));
+        }
+        tid
+    }
+
+    fn micros(&self, time: DateTime<Local>) -> f64 {
+        (time - self.origin).num_nanoseconds().unwrap_or(i64::MAX) as f64 / 1e3
+    }
+}
+
+fn args(fields: &FieldMap) -> Map<String, Value> {
+    fields
+        .iter()
+        .map(|(name, value)| (name.clone(), to_json(value)))
+        .collect()
+}
+
+fn to_json(value: &FieldValue) -> Value {
+    match value {
+        FieldValue::Bool(value) => json!(value),
+        FieldValue::I64(value) => json!(value),
+        FieldValue::U64(value) => json!(value),
+        FieldValue::F64(value) => json!(value),
+        FieldValue::Str(value) | FieldValue::Debug(value) => json!(value),
+        FieldValue::Error { message, sources } => json!({
+            "message": message,
+            "sources": sources,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{capture, capture_timed, TimingLayer, TraceStore};
+
+    fn chrome_trace(store: &TraceStore) -> Vec<Value> {
+        let mut json = Vec::new();
+        store.write_chrome_trace(&mut json).unwrap();
+        let trace: Value = serde_json::from_slice(&json).unwrap();
+        trace["traceEvents"].as_array().unwrap().clone()
+    }
+
+    fn phases(events: &[Value]) -> Vec<(&str, &str)> {
+        events
+            .iter()
+            .map(|event| {
+                (
+                    event["ph"].as_str().unwrap(),
+                    event["name"].as_str().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn spans_are_written_for_each_busy_interval() {
+        let store = capture_timed(TimingLayer::new().with_max_intervals(8), || {
+            let span = tracing::info_span!("request", user_id = 42);
+            span.in_scope(|| tracing::info!(status = 200, "sent"));
+            span.in_scope(|| {});
+        });
+        let events = chrome_trace(&store);
+        assert_eq!(
+            phases(&events),
+            [
+                ("M", "thread_name"),
+                ("b", "request"),
+                ("e", "request"),
+                ("X", "request"),
+                ("X", "request"),
+                ("i", "sent"),
+            ]
+        );
+        assert_eq!(events[1]["args"]["user_id"], 42);
+        assert_eq!(events[3]["args"]["user_id"], 42);
+        assert_eq!(events[5]["args"]["status"], 200);
+        assert_eq!(events[3]["tid"], events[5]["tid"]);
+    }
+
+    #[test]
+    fn spans_are_written_when_their_intervals_are_missing() {
+        let (clock, mock) = quanta::Clock::mock();
+        let store = quanta::with_clock(&clock, || {
+            capture_timed(TimingLayer::new().with_max_intervals(1), || {
+                drop(tracing::info_span!("idle"));
+                let span = tracing::info_span!("request");
+                for _ in 0..3 {
+                    span.in_scope(|| mock.increment(Duration::from_millis(1)));
+                    mock.increment(Duration::from_millis(1));
+                }
+            })
+        });
+        let events = chrome_trace(&store);
+        assert_eq!(
+            phases(&events),
+            [
+                ("M", "thread_name"),
+                ("b", "idle"),
+                ("e", "idle"),
+                ("b", "request"),
+                ("e", "request"),
+                ("X", "request"),
+            ]
+        );
+        let dropped = &events[3]["args"]["dropped_intervals"];
+        assert_eq!(dropped["count"], 2);
+        assert_eq!(dropped["busy_us"], 2000.0);
+        assert_eq!(dropped["end_us"], 3000.0);
+    }
+
+    #[test]
+    fn intervals_are_written_on_the_thread_that_entered_the_span() {
+        let store = capture_timed(TimingLayer::new().with_max_intervals(8), || {
+            let span = tracing::info_span!("request");
+            let dispatch = tracing::dispatcher::get_default(Clone::clone);
+            std::thread::scope(|scope| {
+                std::thread::Builder::new()
+                    .name("worker".to_owned())
+                    .spawn_scoped(scope, || {
+                        tracing::dispatcher::with_default(&dispatch, || {
+                            span.in_scope(|| tracing::info!("working"));
+                        });
+                    })
+                    .unwrap();
+            });
+        });
+        let events = chrome_trace(&store);
+        assert_eq!(
+            phases(&events),
+            [
+                ("M", "thread_name"),
+                ("b", "request"),
+                ("e", "request"),
+                ("M", "thread_name"),
+                ("X", "request"),
+                ("i", "working"),
+            ]
+        );
+        // the lifetime is on the thread that created the span
+        assert_eq!(events[0]["tid"], events[1]["tid"]);
+        assert_eq!(events[3]["args"]["name"], "worker");
+        assert_eq!(events[3]["tid"], events[4]["tid"]);
+        assert_eq!(events[4]["tid"], events[5]["tid"]);
+    }
+
+    #[test]
+    fn spans_without_intervals_cover_their_lifetime() {
+        let store = capture(|| {
+            let _first = tracing::info_span!("first").entered();
+            // a sibling that outlives the first span, so their lifetimes do not nest
+            let second = tracing::info_span!(parent: None, "second");
+            drop(_first);
+            drop(second);
+        });
+        let events = chrome_trace(&store);
+        assert_eq!(
+            phases(&events),
+            [
+                ("M", "thread_name"),
+                ("b", "first"),
+                ("e", "first"),
+                ("b", "second"),
+                ("e", "second"),
+            ]
+        );
+        assert_eq!(events[1]["id"], events[2]["id"]);
+        assert_ne!(events[1]["id"], events[3]["id"]);
+        let ts = |index: usize| events[index]["ts"].as_f64().unwrap();
+        assert!(ts(1) <= ts(3) && ts(2) <= ts(4));
+    }
+
+    #[test]
+    fn folded_stacks_combine_spans_with_the_same_stack() {
+        let (clock, mock) = quanta::Clock::mock();
+        quanta::with_clock(&clock, || {
+            let store = capture(|| {
+                tracing::info_span!("request").in_scope(|| {
+                    mock.increment(Duration::from_millis(1));
+                    for _ in 0..2 {
+                        tracing::info_span!("db_query")
+                            .in_scope(|| mock.increment(Duration::from_millis(1)));
+                    }
+                });
+            });
+            let stacks = |weight| {
+                let mut folded = Vec::new();
+                store.write_folded_stacks(&mut folded, weight).unwrap();
+                String::from_utf8(folded).unwrap()
+            };
+            assert_eq!(
+                stacks(StackWeight::Busy),
+                "request 3000\nrequest;db_query 2000\n"
+            );
+            assert_eq!(
+                stacks(StackWeight::SelfBusy),
+                "request 1000\nrequest;db_query 2000\n"
+            );
+        });
+    }
+}
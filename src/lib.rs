@@ -1,8 +1,62 @@
+mod aggregation;
+mod changes;
 mod display;
+mod export;
+mod query;
+mod retention;
+mod search;
+mod stats;
 mod storage;
 mod timing_layer;
 mod tracing_layer;
+mod widgets;
 
-pub use storage::TraceStore;
-pub use timing_layer::{Timing, TimingLayer};
+pub use aggregation::{Aggregation, EventGroup, GroupBy};
+pub use changes::ChangeSubscription;
+pub use display::{DurationStyle, SpanFormat, StartTime, TimingColumn};
+pub use export::StackWeight;
+pub use query::{ParseQueryError, SpanStatus, TraceQuery};
+pub use retention::RetentionPolicy;
+pub use search::Search;
+pub use stats::SpanStats;
+pub use storage::{
+    EventId, EventRecord, FieldMap, FieldValue, Level, Location, SpanKey, SpanRecord, ThreadInfo,
+    TraceStore,
+};
+pub use timing_layer::{DroppedIntervals, Interval, IntervalLog, Timing, TimingLayer};
 pub use tracing_layer::TracingLayer;
+pub use widgets::{
+    EventGroupList, EventGroupListState, EventListState, EventListWidget, SearchBar,
+    SearchBarState, SpanDetailState, SpanDetailWidget, SpanStatsTable, SpanStatsTableState,
+    StatsColumn, TimelineState, TimelineWidget, TraceTreeState, TraceTreeWidget, TreeItem,
+};
+
+/// Records the spans and events emitted by `f` into a new store.
+#[cfg(test)]
+pub(crate) fn capture(f: impl FnOnce()) -> TraceStore {
+    capture_with(TraceStore::default(), f)
+}
+
+/// Records the spans and events emitted by `f` into the given store.
+#[cfg(test)]
+pub(crate) fn capture_with(store: TraceStore, f: impl FnOnce()) -> TraceStore {
+    capture_into(store, TimingLayer::default(), f)
+}
+
+/// Records the spans and events emitted by `f` into a new store, timing the spans with the given
+/// layer.
+#[cfg(test)]
+pub(crate) fn capture_timed(timing: TimingLayer, f: impl FnOnce()) -> TraceStore {
+    capture_into(TraceStore::default(), timing, f)
+}
+
+#[cfg(test)]
+fn capture_into(store: TraceStore, timing: TimingLayer, f: impl FnOnce()) -> TraceStore {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let subscriber = tracing_subscriber::registry()
+        .with(timing)
+        .with(TracingLayer::with_store(store.clone()));
+    tracing::subscriber::with_default(subscriber, f);
+    store
+}
@@ -0,0 +1,395 @@
+use std::{error::Error, fmt, str::FromStr};
+
+use chrono::{DateTime, Local};
+use tracing::level_filters::LevelFilter;
+
+use crate::{
+    aggregation::EventGroup,
+    storage::{EventRecord, FieldMap, SpanKey, SpanRecord, Spans},
+};
+
+/// A query selecting spans and events from a [`TraceStore`](crate::TraceStore).
+///
+/// Queries are built up from filters which must all match. An event matches if its level and
+/// target pass the level filters, it was recorded within the time range, and the span it was
+/// recorded in passes the span filters. A span matches if its own level and target pass the level
+/// filters, its lifetime overlaps the time range and it passes the span filters.
+///
+/// Levels can be filtered per target using `EnvFilter`-style directives:
+///
+/// ```
+/// use tui_tracing::TraceQuery;
+///
+/// let query: TraceQuery = "my_app=debug,hyper=warn".parse().unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceQuery {
+    level: LevelFilter,
+    directives: Vec<(String, LevelFilter)>,
+    targets: Vec<String>,
+    span_name: Option<String>,
+    fields: Vec<FieldFilter>,
+    since: Option<DateTime<Local>>,
+    until: Option<DateTime<Local>>,
+    status: Option<SpanStatus>,
+}
+
+/// Whether a span is open or closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanStatus {
+    Open,
+    Closed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FieldFilter {
+    Equals(String, String),
+    Contains(String, String),
+}
+
+impl Default for TraceQuery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TraceQuery {
+    /// Create a query that matches everything.
+    pub fn new() -> Self {
+        Self {
+            level: LevelFilter::TRACE,
+            directives: Vec::new(),
+            targets: Vec::new(),
+            span_name: None,
+            fields: Vec::new(),
+            since: None,
+            until: None,
+            status: None,
+        }
+    }
+
+    /// Parse a comma separated list of `EnvFilter`-style directives.
+    ///
+    /// Each directive is either a level (e.g. `warn`), which sets the minimum level, or a target
+    /// prefix with an optional level (e.g. `my_app::db=debug`), which sets the level for targets
+    /// starting with that prefix. The longest matching prefix wins. As with `EnvFilter`, when there
+    /// are target directives but no level directive, targets that match no directive are excluded.
+    /// Span directives (e.g. `my_app[request]` or `[request{id=1}]`) are not supported and return
+    /// an error; use [`with_span_name`](Self::with_span_name) and the field filters instead.
+    pub fn parse_directives(directives: &str) -> Result<Self, ParseQueryError> {
+        let mut query = Self::new();
+        let mut default_level = None;
+        for directive in directives.split(',').map(str::trim) {
+            if directive.is_empty() {
+                continue;
+            }
+            // a span directive may contain commas itself, so any part of one is rejected
+            if directive.contains(['[', ']', '{', '}']) {
+                return Err(ParseQueryError::SpanDirective(directive.to_owned()));
+            }
+            let (target, level) = match directive.split_once('=') {
+                Some((target, level)) => (Some(target.trim()), parse_level(level.trim())?),
+                None => match directive.parse::<LevelFilter>() {
+                    Ok(level) => (None, level),
+                    Err(_) => (Some(directive), LevelFilter::TRACE),
+                },
+            };
+            match target {
+                Some("") => return Err(ParseQueryError::EmptyTarget(directive.to_owned())),
+                Some(target) => query = query.with_directive(target, level),
+                None => default_level = Some(level),
+            }
+        }
+        query.level = match default_level {
+            Some(level) => level,
+            None if query.directives.is_empty() => LevelFilter::TRACE,
+            None => LevelFilter::OFF,
+        };
+        Ok(query)
+    }
+
+    /// Only match spans and events at this level or above.
+    ///
+    /// This applies to targets that do not match a [directive](Self::with_directive).
+    pub fn with_min_level(mut self, level: impl Into<LevelFilter>) -> Self {
+        self.level = level.into();
+        self
+    }
+
+    /// Set the minimum level for targets starting with the given prefix.
+    pub fn with_directive(
+        mut self,
+        target: impl Into<String>,
+        level: impl Into<LevelFilter>,
+    ) -> Self {
+        self.directives.push((target.into(), level.into()));
+        self
+    }
+
+    /// Only match spans and events whose target starts with the given prefix.
+    ///
+    /// When called more than once, targets starting with any of the prefixes match.
+    pub fn with_target(mut self, prefix: impl Into<String>) -> Self {
+        self.targets.push(prefix.into());
+        self
+    }
+
+    /// Only match spans with the given name and events within them, including spans nested
+    /// inside a span with that name.
+    pub fn with_span_name(mut self, name: impl Into<String>) -> Self {
+        self.span_name = Some(name.into());
+        self
+    }
+
+    /// Only match spans and events with a field with the given value.
+    ///
+    /// Fields of the enclosing spans are also considered, so an event matches if it was recorded
+    /// in a span with the field.
+    pub fn with_field_eq(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.fields
+            .push(FieldFilter::Equals(name.into(), value.into()));
+        self
+    }
+
+    /// Only match spans and events with a field whose value contains the given text.
+    ///
+    /// Fields of the enclosing spans are also considered, so an event matches if it was recorded
+    /// in a span with the field.
+    pub fn with_field_contains(mut self, name: impl Into<String>, text: impl Into<String>) -> Self {
+        self.fields
+            .push(FieldFilter::Contains(name.into(), text.into()));
+        self
+    }
+
+    /// Only match events recorded at or after the given time, and spans still open at that time.
+    pub fn with_since(mut self, since: DateTime<Local>) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Only match events recorded at or before the given time, and spans started by that time.
+    pub fn with_until(mut self, until: DateTime<Local>) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    /// Only match spans with the given status and the events within them.
+    pub fn with_status(mut self, status: SpanStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// Get the minimum level for targets that do not match a directive.
+    pub fn min_level(&self) -> LevelFilter {
+        self.level
+    }
+
+    /// Returns whether the query matches everything.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::new()
+    }
+
+    /// Returns whether the span matches the query.
+    pub(crate) fn matches_span(&self, spans: &Spans, key: SpanKey) -> bool {
+        let Some(span) = spans.get(key) else {
+            return false;
+        };
+        let end = span.close_time.unwrap_or_else(Local::now);
+        self.matches_level(&span.level.0, &span.target)
+            && self.since.is_none_or(|since| end >= since)
+            && self.until.is_none_or(|until| span.start_time <= until)
+            && self.matches_context(spans, key, None)
+    }
+
+    /// Returns whether the event, recorded in the given span, matches the query.
+    pub(crate) fn matches_event(&self, spans: &Spans, key: SpanKey, event: &EventRecord) -> bool {
+        self.matches_level(&event.level.0, &event.target)
+            && self.matches_time(event.time)
+            && self.matches_context(spans, key, Some(&event.fields))
+    }
+
+    /// Returns whether the group of events, recorded in the given span, matches the query.
+    ///
+    /// Fields are matched against the most recent event in the group.
+    pub(crate) fn matches_group(&self, spans: &Spans, key: SpanKey, group: &EventGroup) -> bool {
+        let fields = group.samples.back().map(|event| &event.fields);
+        self.matches_level(&group.level.0, &group.target)
+            && self.matches_time(group.last_time)
+            && self.matches_context(spans, key, fields)
+    }
+
+    /// Returns whether the event at the given index in the span matches the query.
+    pub(crate) fn matches_event_at(
+        &self,
+        spans: &Spans,
+        span: &SpanRecord,
+        key: SpanKey,
+        index: usize,
+    ) -> bool {
+        if span.event_groups.is_empty() {
+            self.matches_event(spans, key, &span.events[index])
+        } else {
+            self.matches_group(spans, key, &span.event_groups[index])
+        }
+    }
+
+    fn matches_level(&self, level: &tracing::Level, target: &str) -> bool {
+        let max_level = self
+            .directives
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.level, |(_, level)| *level);
+        let matches_target = self.targets.is_empty()
+            || self
+                .targets
+                .iter()
+                .any(|prefix| target.starts_with(prefix.as_str()));
+        *level <= max_level && matches_target
+    }
+
+    fn matches_time(&self, time: DateTime<Local>) -> bool {
+        self.since.is_none_or(|since| time >= since) && self.until.is_none_or(|until| time <= until)
+    }
+
+    /// Checks the span filters against the span and its ancestors, and the field filters against
+    /// the given fields and the fields of the span and its ancestors.
+    fn matches_context(&self, spans: &Spans, key: SpanKey, fields: Option<&FieldMap>) -> bool {
+        let matches_status = match (self.status, spans.get(key)) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(SpanStatus::Open), Some(span)) => span.close_time.is_none(),
+            (Some(SpanStatus::Closed), Some(span)) => span.close_time.is_some(),
+        };
+        let matches_name = self
+            .span_name
+            .as_ref()
+            .is_none_or(|name| spans.ancestors(key).any(|span| span.name == *name));
+        let matches_fields = self.fields.iter().all(|filter| {
+            fields
+                .into_iter()
+                .chain(spans.ancestors(key).map(|span| &span.fields))
+                .any(|fields| filter.matches(fields))
+        });
+        matches_status && matches_name && matches_fields
+    }
+}
+
+impl FieldFilter {
+    fn matches(&self, fields: &FieldMap) -> bool {
+        match self {
+            FieldFilter::Equals(name, expected) => fields.get(name).is_some_and(|value| {
+                // numbers are compared by value, so that `1.0` matches `1`
+                match (value.as_f64(), expected.parse::<f64>()) {
+                    (Some(value), Ok(expected)) => value == expected,
+                    _ => value.to_string() == *expected,
+                }
+            }),
+            FieldFilter::Contains(name, text) => fields
+                .get(name)
+                .is_some_and(|value| value.to_string().contains(text.as_str())),
+        }
+    }
+}
+
+impl FromStr for TraceQuery {
+    type Err = ParseQueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_directives(s)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, ParseQueryError> {
+    level
+        .parse()
+        .map_err(|_| ParseQueryError::InvalidLevel(level.to_owned()))
+}
+
+/// An error returned when parsing a [`TraceQuery`] from directives fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseQueryError {
+    /// The level of a directive is not a valid level.
+    InvalidLevel(String),
+    /// A directive has a level but no target.
+    EmptyTarget(String),
+    /// A directive filters by span, which is not supported.
+    SpanDirective(String),
+}
+
+impl fmt::Display for ParseQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseQueryError::InvalidLevel(level) => write!(f, "invalid level: {level:?}"),
+            ParseQueryError::EmptyTarget(directive) => {
+                write!(f, "directive has no target: {directive:?}")
+            }
+            ParseQueryError::SpanDirective(directive) => {
+                write!(f, "span directives are not supported: {directive:?}")
+            }
+        }
+    }
+}
+
+impl Error for ParseQueryError {}
+
+#[cfg(test)]
+mod tests {
+    use tracing::Level;
+
+    use super::*;
+
+    #[test]
+    fn parse_directives() {
+        let query: TraceQuery = "my_app=debug, hyper=warn".parse().unwrap();
+        assert_eq!(query.min_level(), LevelFilter::OFF);
+        assert!(query.matches_level(&Level::DEBUG, "my_app::db"));
+        assert!(!query.matches_level(&Level::TRACE, "my_app::db"));
+        assert!(query.matches_level(&Level::WARN, "hyper::client"));
+        assert!(!query.matches_level(&Level::INFO, "hyper::client"));
+        assert!(!query.matches_level(&Level::ERROR, "tokio"));
+    }
+
+    #[test]
+    fn parse_directives_with_default_level() {
+        let query: TraceQuery = "info,my_app::db=trace,my_app=warn".parse().unwrap();
+        assert_eq!(query.min_level(), LevelFilter::INFO);
+        assert!(query.matches_level(&Level::INFO, "tokio"));
+        assert!(!query.matches_level(&Level::DEBUG, "tokio"));
+        assert!(!query.matches_level(&Level::INFO, "my_app::http"));
+        assert!(query.matches_level(&Level::TRACE, "my_app::db"));
+    }
+
+    #[test]
+    fn parse_directives_errors() {
+        assert_eq!(
+            "my_app=loud".parse::<TraceQuery>(),
+            Err(ParseQueryError::InvalidLevel("loud".to_owned()))
+        );
+        assert_eq!(
+            "=warn".parse::<TraceQuery>(),
+            Err(ParseQueryError::EmptyTarget("=warn".to_owned()))
+        );
+        assert_eq!(
+            "my_app[request]=debug".parse::<TraceQuery>(),
+            Err(ParseQueryError::SpanDirective(
+                "my_app[request]=debug".to_owned()
+            ))
+        );
+        assert_eq!(
+            "[request{id}]".parse::<TraceQuery>(),
+            Err(ParseQueryError::SpanDirective("[request{id}]".to_owned()))
+        );
+        assert!("warn,[request{id=1,user=2}]".parse::<TraceQuery>().is_err());
+    }
+
+    #[test]
+    fn target_prefixes_and_min_level() {
+        let query = TraceQuery::new()
+            .with_min_level(Level::WARN)
+            .with_target("my_app::db");
+        assert!(query.matches_level(&Level::ERROR, "my_app::db::pool"));
+        assert!(!query.matches_level(&Level::INFO, "my_app::db"));
+        assert!(!query.matches_level(&Level::ERROR, "my_app::http"));
+    }
+}
@@ -0,0 +1,96 @@
+use chrono::Duration;
+
+/// Limits on how many spans and events a [`TraceStore`](crate::TraceStore) keeps.
+///
+/// The policy is enforced whenever spans and events are inserted or closed, evicting the oldest
+/// data first. By default nothing is evicted.
+///
+/// Closed spans that recorded a WARN or ERROR event can be kept for longer than other spans with
+/// [`with_warning_max_age`](Self::with_warning_max_age), and are evicted last when there are more
+/// than [`max_closed_spans`](Self::max_closed_spans).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    max_closed_spans: Option<usize>,
+    max_events_per_span: Option<usize>,
+    max_events: Option<usize>,
+    max_age: Option<Duration>,
+    warning_max_age: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    /// Create a policy that keeps everything.
+    pub const fn new() -> Self {
+        Self {
+            max_closed_spans: None,
+            max_events_per_span: None,
+            max_events: None,
+            max_age: None,
+            warning_max_age: None,
+        }
+    }
+
+    /// Set the maximum number of closed spans to keep.
+    pub const fn with_max_closed_spans(mut self, max: usize) -> Self {
+        self.max_closed_spans = Some(max);
+        self
+    }
+
+    /// Set the maximum number of events to keep in each span.
+    ///
+    /// When events are aggregated, this limits the number of event groups instead.
+    pub const fn with_max_events_per_span(mut self, max: usize) -> Self {
+        self.max_events_per_span = Some(max);
+        self
+    }
+
+    /// Set the maximum number of events to keep across all spans.
+    ///
+    /// When events are aggregated, this limits the number of event groups instead.
+    pub const fn with_max_events(mut self, max: usize) -> Self {
+        self.max_events = Some(max);
+        self
+    }
+
+    /// Set how long closed spans are kept after they close, and events after they are recorded.
+    ///
+    /// Events age out in open spans and outside of any span too.
+    pub const fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Set how long closed spans that recorded a WARN or ERROR event are kept after they close.
+    ///
+    /// If this is not set, such spans are kept for [`max_age`](Self::max_age) like other spans.
+    /// When `max_age` is set, the events of such spans are also kept for this long after they are
+    /// recorded.
+    pub const fn with_warning_max_age(mut self, max_age: Duration) -> Self {
+        self.warning_max_age = Some(max_age);
+        self
+    }
+
+    /// Get the maximum number of closed spans to keep.
+    pub fn max_closed_spans(&self) -> Option<usize> {
+        self.max_closed_spans
+    }
+
+    /// Get the maximum number of events to keep in each span.
+    pub fn max_events_per_span(&self) -> Option<usize> {
+        self.max_events_per_span
+    }
+
+    /// Get the maximum number of events to keep across all spans.
+    pub fn max_events(&self) -> Option<usize> {
+        self.max_events
+    }
+
+    /// Get how long closed spans are kept after they close.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// Get how long closed spans that recorded a WARN or ERROR event are kept after they close.
+    pub fn warning_max_age(&self) -> Option<Duration> {
+        self.warning_max_age
+    }
+}
@@ -0,0 +1,201 @@
+use std::ops::Range;
+
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span},
+};
+use regex::Regex;
+
+use crate::{
+    aggregation::EventGroup,
+    storage::{EventRecord, FieldMap, SpanRecord},
+};
+
+/// A text search over the spans and events in a [`TraceStore`](crate::TraceStore).
+///
+/// Matches span names, targets, event messages and field values. Set it on a
+/// [`TraceTreeWidget`](crate::TraceTreeWidget) or [`EventListWidget`](crate::EventListWidget) to
+/// highlight the matches, and use [`TraceTreeState::select_next_match`] to jump between them.
+///
+/// [`TraceTreeState::select_next_match`]: crate::TraceTreeState::select_next_match
+#[derive(Debug, Clone)]
+pub struct Search {
+    regex: Regex,
+    style: Style,
+}
+
+impl Search {
+    /// Search for a substring.
+    ///
+    /// The search ignores case unless the text contains an uppercase letter.
+    pub fn substring(text: &str) -> Self {
+        let ignore_case = !text.chars().any(char::is_uppercase);
+        // the flag is part of the pattern rather than the builder, so that the pattern tells
+        // searches apart
+        let flags = if ignore_case { "(?i)" } else { "" };
+        let regex = Regex::new(&format!("{flags}{}", regex::escape(text)))
+            .expect("escaped text is a valid regex");
+        Self::with_regex(regex)
+    }
+
+    /// Search for matches of a regular expression.
+    pub fn regex(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(Self::with_regex)
+    }
+
+    fn with_regex(regex: Regex) -> Self {
+        Self {
+            regex,
+            style: Style::new().fg(Color::Black).bg(Color::Yellow),
+        }
+    }
+
+    /// Set the style patched onto highlighted matches.
+    pub fn with_style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Get the pattern of the search, which decides what it matches.
+    pub(crate) fn pattern(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    /// Returns whether the text contains a match.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+
+    /// Returns whether the span's name, target or field values contain a match.
+    pub fn matches_span(&self, span: &SpanRecord) -> bool {
+        self.is_match(&span.name)
+            || self.is_match(&span.target)
+            || self.matches_fields(&span.fields)
+    }
+
+    /// Returns whether the event's target or field values (including its message) contain a match.
+    pub fn matches_event(&self, event: &EventRecord) -> bool {
+        self.is_match(event.target()) || self.matches_fields(event.fields())
+    }
+
+    /// Returns whether the group's message or target, or the field values of any sampled event,
+    /// contain a match.
+    pub fn matches_group(&self, group: &EventGroup) -> bool {
+        group
+            .message()
+            .is_some_and(|message| self.is_match(message))
+            || self.is_match(&group.target)
+            || group.samples.iter().any(|event| self.matches_event(event))
+    }
+
+    fn matches_fields(&self, fields: &FieldMap) -> bool {
+        fields
+            .values()
+            .any(|value| self.is_match(&value.to_string()))
+    }
+
+    /// Highlights the matches in the line, splitting its spans where a match starts or ends.
+    ///
+    /// Matches may span several of the line's spans.
+    pub fn highlight<'a>(&self, line: Line<'a>) -> Line<'a> {
+        let len = line.spans.len();
+        self.highlight_spans(line, 0..len)
+    }
+
+    /// Highlights the matches in the given spans of the line, leaving the other spans as they
+    /// are, so that only the text the search checks is highlighted.
+    ///
+    /// Matches may span several of the given spans.
+    pub(crate) fn highlight_spans<'a>(&self, mut line: Line<'a>, spans: Range<usize>) -> Line<'a> {
+        let end = spans.end.min(line.spans.len());
+        let after = line.spans.split_off(end);
+        let searched = line.spans.split_off(spans.start.min(end));
+        line.spans.extend(self.highlight_in(searched));
+        line.spans.extend(after);
+        line
+    }
+
+    fn highlight_in<'a>(&self, spans: Vec<Span<'a>>) -> Vec<Span<'a>> {
+        let text: String = spans.iter().map(|span| &*span.content).collect();
+        let matches: Vec<Range<usize>> = self
+            .regex
+            .find_iter(&text)
+            .filter(|found| !found.is_empty())
+            .map(|found| found.range())
+            .collect();
+        if matches.is_empty() {
+            return spans;
+        }
+
+        let mut highlighted = Vec::new();
+        let mut start = 0;
+        for span in spans {
+            let end = start + span.content.len();
+            let mut pos = start;
+            for found in matches
+                .iter()
+                .filter(|found| found.start < end && found.end > start)
+            {
+                let (found_start, found_end) = (found.start.max(start), found.end.min(end));
+                if pos < found_start {
+                    highlighted.push(Span::styled(text[pos..found_start].to_owned(), span.style));
+                }
+                let style = span.style.patch(self.style);
+                highlighted.push(Span::styled(text[found_start..found_end].to_owned(), style));
+                pos = found_end;
+            }
+            if pos == start {
+                highlighted.push(span);
+            } else if pos < end {
+                highlighted.push(Span::styled(text[pos..end].to_owned(), span.style));
+            }
+            start = end;
+        }
+        highlighted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::style::Modifier;
+
+    use super::*;
+
+    #[test]
+    fn substring_ignores_case_unless_uppercase() {
+        assert!(Search::substring("get").is_match("GET /users"));
+        assert!(!Search::substring("Get").is_match("GET /users"));
+        assert!(Search::substring("a.b").is_match("a.b"));
+        assert!(!Search::substring("a.b").is_match("axb"));
+    }
+
+    #[test]
+    fn highlight_splits_spans() {
+        let search =
+            Search::substring("lo w").with_style(Style::new().add_modifier(Modifier::BOLD));
+        let line = Line::from(vec![Span::raw("hello "), Span::raw("world")]);
+        let line = search.highlight(line);
+        let bold = Style::new().add_modifier(Modifier::BOLD);
+        assert_eq!(
+            line.spans,
+            [
+                Span::raw("hel"),
+                Span::styled("lo ", bold),
+                Span::styled("w", bold),
+                Span::raw("orld"),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlight_spans_leaves_other_spans() {
+        let search = Search::substring("id").with_style(Style::new().add_modifier(Modifier::BOLD));
+        let line = Line::from(vec![Span::raw("id "), Span::raw("id=1")]);
+        let line = search.highlight_spans(line, 1..2);
+        let bold = Style::new().add_modifier(Modifier::BOLD);
+        assert_eq!(
+            line.spans,
+            [Span::raw("id "), Span::styled("id", bold), Span::raw("=1")]
+        );
+    }
+}
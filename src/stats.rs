@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use hdrhistogram::Histogram;
+
+use crate::storage::SpanRecord;
+
+/// Latency statistics for the closed spans from one callsite, identified by target and name.
+///
+/// Created by the [`TraceStore`](crate::TraceStore) as spans close, and kept when the spans
+/// themselves are removed by the retention policy or [`remove_expired`]. Durations are recorded
+/// in histograms with three significant digits, so percentiles are accurate to within 0.1%.
+///
+/// [`remove_expired`]: crate::TraceStore::remove_expired
+#[derive(Debug, Clone)]
+pub struct SpanStats {
+    target: String,
+    name: String,
+    count: u64,
+    warn_events: u64,
+    error_events: u64,
+    /// Durations in nanoseconds.
+    total: Histogram<u64>,
+    busy: Histogram<u64>,
+}
+
+impl SpanStats {
+    pub(crate) fn new(target: &str, name: &str) -> Self {
+        Self {
+            target: target.to_owned(),
+            name: name.to_owned(),
+            count: 0,
+            warn_events: 0,
+            error_events: 0,
+            total: Histogram::new(3).expect("3 significant digits are supported"),
+            busy: Histogram::new(3).expect("3 significant digits are supported"),
+        }
+    }
+
+    /// Adds a closed span to the statistics.
+    pub(crate) fn record(&mut self, span: &SpanRecord) {
+        self.count += 1;
+        self.warn_events += span.warn_count;
+        self.error_events += span.error_count;
+        self.total
+            .saturating_record(as_nanos(span.timing.total_duration()));
+        self.busy
+            .saturating_record(as_nanos(span.timing.busy_duration()));
+    }
+
+    /// Get the target of the spans.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// Get the name of the spans.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the number of closed spans.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Get the number of warning events recorded in the spans.
+    pub fn warn_events(&self) -> u64 {
+        self.warn_events
+    }
+
+    /// Get the number of error events recorded in the spans.
+    pub fn error_events(&self) -> u64 {
+        self.error_events
+    }
+
+    /// Get the total duration at the given quantile (e.g. `0.99` for p99).
+    pub fn total_quantile(&self, quantile: f64) -> Duration {
+        Duration::from_nanos(self.total.value_at_quantile(quantile))
+    }
+
+    /// Get the busy duration at the given quantile (e.g. `0.99` for p99).
+    pub fn busy_quantile(&self, quantile: f64) -> Duration {
+        Duration::from_nanos(self.busy.value_at_quantile(quantile))
+    }
+
+    /// Get the mean total duration.
+    pub fn total_mean(&self) -> Duration {
+        Duration::from_secs_f64(self.total.mean() / 1e9)
+    }
+
+    /// Get the mean busy duration.
+    pub fn busy_mean(&self) -> Duration {
+        Duration::from_secs_f64(self.busy.mean() / 1e9)
+    }
+
+    /// Get the longest total duration.
+    pub fn total_max(&self) -> Duration {
+        Duration::from_nanos(self.total.max())
+    }
+
+    /// Get the longest busy duration.
+    pub fn busy_max(&self) -> Duration {
+        Duration::from_nanos(self.busy.max())
+    }
+
+    /// Get the histogram of total durations, in nanoseconds.
+    pub fn total_histogram(&self) -> &Histogram<u64> {
+        &self.total
+    }
+
+    /// Get the histogram of busy durations, in nanoseconds.
+    pub fn busy_histogram(&self) -> &Histogram<u64> {
+        &self.busy
+    }
+}
+
+fn as_nanos(duration: Duration) -> u64 {
+    duration.as_nanos().try_into().unwrap_or(u64::MAX)
+}
@@ -1,78 +1,698 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    fmt, io, iter,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, ThreadId},
+};
 
 use chrono::{DateTime, Duration, Local};
 use indexmap::IndexMap;
-use parking_lot::RwLock;
-use tracing::field::Visit;
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tokio::sync::watch;
+use tracing::{
+    callsite::Identifier,
+    field::{Field, Visit},
+    span::Attributes,
+    Metadata,
+};
 use tracing_subscriber::{
     field::VisitOutput,
     registry::{LookupSpan, SpanRef},
 };
 
-use crate::Timing;
+use crate::{
+    aggregation::{Aggregation, EventGroup},
+    changes::ChangeSubscription,
+    export::{self, StackWeight},
+    query::TraceQuery,
+    retention::RetentionPolicy,
+    stats::SpanStats,
+    Timing,
+};
 
 #[derive(Debug, Clone)]
 pub struct TraceStore {
-    pub(crate) spans: Arc<RwLock<IndexMap<u64, SpanRecord>>>,
+    pub(crate) spans: Arc<RwLock<Spans>>,
+    next_key: Arc<AtomicU64>,
+    aggregation: Option<Aggregation>,
+    retention: RetentionPolicy,
+    /// The generation of the store, incremented on every change.
+    changes: Arc<watch::Sender<u64>>,
+}
+
+/// The spans in a [`TraceStore`], along with the bookkeeping needed to enforce its
+/// [`RetentionPolicy`].
+#[derive(Debug, Default)]
+pub(crate) struct Spans {
+    /// The records by key. Keys are allocated in increasing order, so this is creation order.
+    records: BTreeMap<SpanKey, Arc<SpanRecord>>,
+    /// The keys of the closed spans without warnings, in the order they were closed.
+    closed: VecDeque<SpanKey>,
+    /// The keys of the closed spans with warnings, in the order they were closed.
+    closed_with_warnings: VecDeque<SpanKey>,
+    /// The keys of the spans with stored events, paired with whether the span is still open so
+    /// that events are taken from closed spans first.
+    with_events: BTreeSet<(bool, SpanKey)>,
+    /// The number of events (or event groups) stored across all spans.
+    event_count: usize,
+    /// The time and span of each event recorded while events age out, oldest first.
+    event_times: VecDeque<(DateTime<Local>, SpanKey)>,
+    /// The entries of `event_times` for spans with warnings, which may age out later.
+    warning_event_times: VecDeque<(DateTime<Local>, SpanKey)>,
+    /// The id of the next event recorded in the store.
+    next_event_id: u64,
+    /// The statistics of the closed spans, by target and name.
+    stats: IndexMap<(String, String), SpanStats>,
+}
+
+/// A key identifying a span in a [`TraceStore`].
+///
+/// Unlike [`span::Id`](tracing::span::Id), which the registry reuses once a span closes, a key is
+/// never reused for the lifetime of the store, so records of closed spans are never overwritten by
+/// new spans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpanKey(u64);
+
+impl SpanKey {
+    /// The key of the root span, which holds events that occur outside of any span.
+    pub const ROOT: Self = Self(0);
+
+    /// Returns the key as a `u64`.
+    pub fn into_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// An id identifying an event in a [`TraceStore`].
+///
+/// Ids are allocated in the order events are recorded and are never reused for the lifetime of the
+/// store, so an event keeps its id as older events are evicted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EventId(u64);
+
+impl EventId {
+    /// Returns the id as a `u64`.
+    pub fn into_u64(self) -> u64 {
+        self.0
+    }
 }
 
 impl Default for TraceStore {
     fn default() -> Self {
-        let mut map = IndexMap::new();
+        let mut spans = Spans::default();
         // Insert a root span to ensure there is always at least one span in the map.
-        map.insert(
-            0,
-            SpanRecord {
+        spans.records.insert(
+            SpanKey::ROOT,
+            Arc::new(SpanRecord {
                 start_time: Local::now(),
                 close_time: None,
                 timing: Timing::default(),
                 level: Level(tracing::Level::INFO),
                 name: "root".to_owned(),
                 target: "root".to_owned(),
-                events: Vec::new(),
-            },
+                location: Location::default(),
+                thread: None,
+                parent: None,
+                children: Vec::new(),
+                fields: FieldMap::new(),
+                events: VecDeque::new(),
+                event_groups: Vec::new(),
+                warn_count: 0,
+                error_count: 0,
+            }),
         );
         Self {
-            spans: Arc::new(RwLock::new(map)),
+            spans: Arc::new(RwLock::new(spans)),
+            next_key: Arc::new(AtomicU64::new(1)),
+            aggregation: None,
+            retention: RetentionPolicy::default(),
+            changes: Arc::new(watch::Sender::new(0)),
         }
     }
 }
 
 impl TraceStore {
-    pub fn spans(&self) -> Vec<SpanRecord> {
+    /// Groups repeated events instead of storing each one individually.
+    ///
+    /// When aggregation is enabled, events are stored in [`SpanRecord::event_groups`] rather than
+    /// [`SpanRecord::events`]. This must be configured before the store is passed to a
+    /// [`TracingLayer`](crate::TracingLayer).
+    pub fn with_aggregation(mut self, aggregation: Aggregation) -> Self {
+        self.aggregation = Some(aggregation);
+        self
+    }
+
+    /// Limits the spans and events kept in the store.
+    ///
+    /// The policy is enforced as spans and events are recorded. This must be configured before
+    /// the store is passed to a [`TracingLayer`](crate::TracingLayer).
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Get the retention policy of the store.
+    pub fn retention(&self) -> RetentionPolicy {
+        self.retention
+    }
+
+    /// Get the generation of the store.
+    ///
+    /// The generation is incremented every time a span or event is recorded, so comparing
+    /// generations is a cheap way to tell whether anything has changed.
+    pub fn generation(&self) -> u64 {
+        *self.changes.borrow()
+    }
+
+    /// Subscribe to changes in the store.
+    pub fn subscribe(&self) -> ChangeSubscription {
+        ChangeSubscription::new(self.changes.subscribe())
+    }
+
+    /// Returns the number of spans in the store, including the root span.
+    pub fn len(&self) -> usize {
+        self.spans.read().records.len()
+    }
+
+    /// Returns whether the store has no spans, which is never the case as it keeps a root span.
+    pub fn is_empty(&self) -> bool {
+        self.spans.read().records.is_empty()
+    }
+
+    /// Returns a snapshot of the spans in creation order.
+    ///
+    /// Records are shared with the store rather than copied, so taking a snapshot is cheap. The
+    /// store copies a record before changing it if a snapshot still refers to it.
+    pub fn spans(&self) -> Vec<Arc<SpanRecord>> {
         let spans = self.spans.read();
-        spans.values().cloned().collect()
+        spans.records.values().cloned().collect()
+    }
+
+    /// Calls `f` with an iterator over the spans in creation order, without copying them.
+    ///
+    /// The store is locked for reading while `f` runs, so `f` should not take long.
+    pub fn with_spans<R>(
+        &self,
+        f: impl FnOnce(&mut dyn Iterator<Item = (SpanKey, &SpanRecord)>) -> R,
+    ) -> R {
+        let spans = self.spans.read();
+        let mut iter = spans.iter();
+        f(&mut iter)
+    }
+
+    /// Returns the spans matching the query, in creation order.
+    pub fn query_spans(&self, query: &TraceQuery) -> Vec<(SpanKey, Arc<SpanRecord>)> {
+        let spans = self.spans.read();
+        spans
+            .records
+            .iter()
+            .filter(|(&key, _)| query.matches_span(&spans, key))
+            .map(|(&key, span)| (key, span.clone()))
+            .collect()
+    }
+
+    /// Returns the events matching the query, oldest first, paired with the key of the span they
+    /// were recorded in.
+    ///
+    /// When events are aggregated, the sampled events of the matching groups are returned.
+    pub fn query_events(&self, query: &TraceQuery) -> Vec<(SpanKey, EventRecord)> {
+        let spans = &*self.spans.read();
+        let mut events: Vec<(SpanKey, EventRecord)> = spans
+            .iter()
+            .flat_map(|(key, span)| {
+                let events = span
+                    .events
+                    .iter()
+                    .filter(move |event| query.matches_event(spans, key, event));
+                let groups = span
+                    .event_groups
+                    .iter()
+                    .filter(move |group| query.matches_group(spans, key, group))
+                    .flat_map(|group| &group.samples);
+                events.chain(groups).map(move |event| (key, event.clone()))
+            })
+            .collect();
+        events.sort_by_key(|(_, event)| event.time);
+        events
     }
 
-    pub fn insert_span(&self, id: u64, span: SpanRecord) {
-        let mut spans = self.spans.write();
-        spans.insert(id, span);
+    /// Returns the statistics of the closed spans from each callsite, in the order the callsites
+    /// were first closed.
+    pub fn span_stats(&self) -> Vec<SpanStats> {
+        self.spans.read().stats.values().cloned().collect()
     }
 
-    pub fn insert_event(&self, span_id: u64, event: EventRecord) {
-        let mut spans = self.spans.write();
-        if let Some(span) = spans.get_mut(&span_id) {
-            span.events.push(event);
+    /// Returns the statistics of the closed spans with the given target and name.
+    pub fn span_stats_for(&self, target: &str, name: &str) -> Option<SpanStats> {
+        self.spans
+            .read()
+            .stats
+            .get(&(target.to_owned(), name.to_owned()))
+            .cloned()
+    }
+
+    /// Writes the spans and events as JSON in the [Chrome Trace Event format], which can be opened
+    /// in [Perfetto](https://ui.perfetto.dev) or `chrome://tracing`.
+    ///
+    /// Spans are drawn over the intervals they were busy, on the thread they were entered on, when
+    /// the [`TimingLayer`](crate::TimingLayer) records them, and over their lifetime otherwise.
+    /// The store is only locked while the spans are copied, not while they are written.
+    ///
+    /// [Chrome Trace Event format]: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+    pub fn write_chrome_trace(&self, writer: impl io::Write) -> io::Result<()> {
+        export::write_chrome_trace(&self.snapshot(), writer)
+    }
+
+    /// Writes the spans as folded stacks, one line per stack of span names followed by its weight
+    /// in microseconds, e.g. `request;db_query 1234`.
+    ///
+    /// The output can be turned into a flame graph by `inferno-flamegraph` or `flamegraph.pl`.
+    /// With the default [`StackWeight::SelfBusy`], each frame is as wide as the busy time of its
+    /// spans.
+    pub fn write_folded_stacks(
+        &self,
+        writer: impl io::Write,
+        weight: StackWeight,
+    ) -> io::Result<()> {
+        export::write_folded_stacks(&self.snapshot(), writer, weight)
+    }
+
+    /// Returns the spans in depth-first order, paired with their depth in the tree.
+    ///
+    /// Spans without a parent (or whose parent has already been removed) are treated as roots and
+    /// are returned in creation order, each followed by its descendants.
+    pub fn tree(&self) -> Vec<(usize, SpanKey, Arc<SpanRecord>)> {
+        let spans = self.spans.read();
+        spans
+            .depth_first(spans.roots())
+            .into_iter()
+            .map(|(depth, key, span)| (depth, key, span.clone()))
+            .collect()
+    }
+
+    /// Returns the keys of the spans that have no parent in the store.
+    pub fn roots(&self) -> Vec<SpanKey> {
+        self.spans.read().roots().collect()
+    }
+
+    /// Returns the keys of the direct children of the given span.
+    pub fn children(&self, key: SpanKey) -> Vec<SpanKey> {
+        self.spans
+            .read()
+            .records
+            .get(&key)
+            .map(|span| span.children.clone())
+            .unwrap_or_default()
+    }
+
+    /// Get the record of the span with the given key, if it is still in the store.
+    pub fn get(&self, key: SpanKey) -> Option<Arc<SpanRecord>> {
+        self.spans.read().records.get(&key).cloned()
+    }
+
+    /// Returns a copy of the spans that shares the records with the store, so that they can be
+    /// read without holding the lock.
+    pub(crate) fn snapshot(&self) -> Spans {
+        self.spans.read().snapshot()
+    }
+
+    /// Locks the spans for reading.
+    pub(crate) fn read(&self) -> RwLockReadGuard<'_, Spans> {
+        self.spans.read()
+    }
+
+    /// Locks the spans for writing.
+    fn write(&self) -> RwLockWriteGuard<'_, Spans> {
+        self.spans.write()
+    }
+
+    /// Marks the store as changed.
+    ///
+    /// This is called while the write lock is still held, so subscribers see the change once they
+    /// can read.
+    fn changed(&self) {
+        self.changes.send_modify(|generation| *generation += 1);
+    }
+
+    /// Returns an id identifying the store, shared by its clones.
+    pub(crate) fn id(&self) -> usize {
+        Arc::as_ptr(&self.spans) as usize
+    }
+
+    /// Allocates a new key that is unique for the lifetime of the store.
+    pub(crate) fn next_key(&self) -> SpanKey {
+        SpanKey(self.next_key.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn insert_span(&self, key: SpanKey, span: SpanRecord) {
+        let mut spans = self.write();
+        if let Some(parent) = span
+            .parent
+            .and_then(|parent| spans.records.get_mut(&parent))
+            .map(Arc::make_mut)
+        {
+            parent.children.push(key);
         }
+        spans.records.insert(key, Arc::new(span));
+        spans.remove_aged(&self.retention, Local::now());
+        self.changed();
     }
 
-    pub fn close_span(&self, id: u64) {
-        self.spans.write().get_mut(&id).unwrap().close();
+    pub fn insert_event(&self, key: SpanKey, mut event: EventRecord) {
+        let mut spans = self.write();
+        let spans = &mut *spans;
+        let Some(span) = spans.records.get_mut(&key).map(Arc::make_mut) else {
+            return;
+        };
+        event.id = EventId(spans.next_event_id);
+        spans.next_event_id += 1;
+        if self.retention.max_age().is_some() {
+            spans.event_times.push_back((event.time, key));
+        }
+        let before = span.event_len();
+        span.count_event(&event);
+        match self.aggregation {
+            Some(aggregation) => aggregation.insert(&mut span.event_groups, event),
+            None => span.events.push_back(event),
+        }
+        if let Some(max) = self.retention.max_events_per_span() {
+            while span.event_len() > max {
+                span.pop_event();
+            }
+        }
+        let after = span.event_len();
+        let open = span.close_time.is_none();
+        spans.event_count = spans.event_count + after - before;
+        if after > 0 {
+            spans.with_events.insert((open, key));
+        } else {
+            spans.with_events.remove(&(open, key));
+        }
+        if let Some(max) = self.retention.max_events() {
+            spans.remove_oldest_events(max);
+        }
+        spans.remove_aged(&self.retention, Local::now());
+        self.changed();
+    }
+
+    pub fn close_span(&self, key: SpanKey) {
+        let mut spans = self.write();
+        let spans = &mut *spans;
+        let Some(span) = spans.records.get_mut(&key).map(Arc::make_mut) else {
+            return;
+        };
+        span.close();
+        // the stats only cover user spans, see `Spans::user_spans`
+        if key != SpanKey::ROOT {
+            spans
+                .stats
+                .entry((span.target.clone(), span.name.clone()))
+                .or_insert_with(|| SpanStats::new(&span.target, &span.name))
+                .record(span);
+        }
+        if span.has_warnings() {
+            spans.closed_with_warnings.push_back(key);
+        } else {
+            spans.closed.push_back(key);
+        }
+        if spans.with_events.remove(&(true, key)) {
+            spans.with_events.insert((false, key));
+        }
+        if let Some(max) = self.retention.max_closed_spans() {
+            spans.remove_oldest_closed(max);
+        }
+        spans.remove_aged(&self.retention, Local::now());
+        self.changed();
     }
 
     pub fn remove_expired(&self, threshold: Duration) {
-        let mut spans = self.spans.write();
-        spans.retain(|_, span| {
+        let mut spans = self.write();
+        let len = spans.records.len();
+        spans.records.retain(|_, span| {
             !span.close_time.is_some_and(|close_time| {
                 Local::now().signed_duration_since(close_time) > threshold
             })
         });
+        if spans.records.len() < len {
+            spans.rebuild();
+            self.changed();
+        }
+    }
+
+    /// Merges newly recorded field values into the span's fields.
+    ///
+    /// Values for fields that were already recorded are replaced.
+    pub fn record_fields(&self, key: SpanKey, fields: FieldMap) {
+        let mut spans = self.write();
+        if let Some(span) = spans.records.get_mut(&key).map(Arc::make_mut) {
+            span.fields.extend(fields);
+            self.changed();
+        }
+    }
+
+    pub(crate) fn update_timing(&self, key: SpanKey, timing: &Timing) {
+        let mut spans = self.write();
+        if let Some(span) = spans.records.get_mut(&key).map(Arc::make_mut) {
+            span.timing.clone_from(timing);
+            self.changed();
+        }
+    }
+}
+
+impl Spans {
+    /// Get the record of the span with the given key.
+    pub(crate) fn get(&self, key: SpanKey) -> Option<&SpanRecord> {
+        self.records.get(&key).map(AsRef::as_ref)
+    }
+
+    /// Returns the spans in creation order.
+    pub(crate) fn iter(&self) -> impl DoubleEndedIterator<Item = (SpanKey, &SpanRecord)> {
+        self.records.iter().map(|(&key, span)| (key, span.as_ref()))
+    }
+
+    /// Returns the spans recorded by the application in creation order, leaving out the root span.
+    ///
+    /// The root span only holds the events recorded outside of any span. It is never entered or
+    /// closed, so it has no timing of its own to show or export.
+    pub(crate) fn user_spans(&self) -> impl DoubleEndedIterator<Item = (SpanKey, &SpanRecord)> {
+        self.iter().filter(|&(key, _)| key != SpanKey::ROOT)
+    }
+
+    /// Returns the user spans in depth-first order, paired with their depth in the tree.
+    pub(crate) fn user_tree(&self) -> Vec<(usize, SpanKey, &Arc<SpanRecord>)> {
+        let roots = self
+            .user_spans()
+            .filter(|(_, span)| self.is_root(span))
+            .map(|(key, _)| key);
+        self.depth_first(roots)
+    }
+
+    /// Returns the span and its ancestors that are still in the store, innermost first.
+    pub(crate) fn ancestors(&self, key: SpanKey) -> impl Iterator<Item = &SpanRecord> {
+        iter::successors(self.get(key), |span| {
+            span.parent.and_then(|parent| self.get(parent))
+        })
+    }
+
+    /// Returns the span and its ancestors that are still in the store, outermost first.
+    pub(crate) fn path(&self, key: SpanKey) -> Vec<&SpanRecord> {
+        let mut path: Vec<&SpanRecord> = self.ancestors(key).collect();
+        path.reverse();
+        path
+    }
+
+    /// Returns a copy of the records, shared with the store, without the retention bookkeeping.
+    pub(crate) fn snapshot(&self) -> Spans {
+        Spans {
+            records: self.records.clone(),
+            ..Spans::default()
+        }
+    }
+
+    /// Returns the statistics of the closed spans from each callsite.
+    pub(crate) fn stats(&self) -> impl Iterator<Item = &SpanStats> {
+        self.stats.values()
+    }
+
+    /// Returns the keys of the spans whose parent is not in the store, in creation order.
+    pub(crate) fn roots(&self) -> impl DoubleEndedIterator<Item = SpanKey> + '_ {
+        self.iter()
+            .filter(|(_, span)| self.is_root(span))
+            .map(|(key, _)| key)
+    }
+
+    /// Returns whether the span's parent is not in the store.
+    fn is_root(&self, span: &SpanRecord) -> bool {
+        !span
+            .parent
+            .is_some_and(|parent| self.records.contains_key(&parent))
+    }
+
+    /// Returns the given roots in order, each followed by its descendants, paired with their depth.
+    fn depth_first(
+        &self,
+        roots: impl DoubleEndedIterator<Item = SpanKey>,
+    ) -> Vec<(usize, SpanKey, &Arc<SpanRecord>)> {
+        let mut tree = Vec::with_capacity(self.records.len());
+        let mut stack: Vec<(usize, SpanKey)> = roots.rev().map(|key| (0, key)).collect();
+        while let Some((depth, key)) = stack.pop() {
+            let Some(span) = self.records.get(&key) else {
+                continue;
+            };
+            stack.extend(span.children.iter().rev().map(|&child| (depth + 1, child)));
+            tree.push((depth, key, span));
+        }
+        tree
+    }
+
+    /// Removes a span, unlinking it from its parent.
+    ///
+    /// The span is not removed from the closed spans, so callers pop it from there themselves.
+    fn remove(&mut self, key: SpanKey) -> Option<Arc<SpanRecord>> {
+        let span = self.records.remove(&key)?;
+        if let Some(parent) = span
+            .parent
+            .and_then(|parent| self.records.get_mut(&parent))
+            .map(Arc::make_mut)
+        {
+            parent.children.retain(|&child| child != key);
+        }
+        self.with_events.remove(&(span.close_time.is_none(), key));
+        self.event_count -= span.event_len();
+        Some(span)
     }
 
-    pub(crate) fn update_timing(&self, into_u64: u64, timing: &Timing) {
-        let mut spans = self.spans.write();
-        if let Some(span) = spans.get_mut(&into_u64) {
-            span.timing = timing.clone();
+    /// Recomputes the bookkeeping after spans have been removed from the records directly.
+    fn rebuild(&mut self) {
+        let keys: HashSet<SpanKey> = self.records.keys().copied().collect();
+        for span in self.records.values_mut() {
+            // only copy records that are shared with a snapshot if they actually change
+            if !span.children.iter().all(|child| keys.contains(child)) {
+                Arc::make_mut(span)
+                    .children
+                    .retain(|child| keys.contains(child));
+            }
+        }
+        self.closed.retain(|key| keys.contains(key));
+        self.closed_with_warnings.retain(|key| keys.contains(key));
+        self.with_events.retain(|(_, key)| keys.contains(key));
+        self.event_count = self.records.values().map(|span| span.event_len()).sum();
+    }
+
+    /// Removes closed spans and events that are older than the policy allows.
+    fn remove_aged(&mut self, retention: &RetentionPolicy, now: DateTime<Local>) {
+        if let Some(max_age) = retention.max_age() {
+            while let Some(key) = Self::pop_aged(&mut self.closed, &self.records, max_age, now) {
+                self.remove(key);
+            }
+        }
+        if let Some(max_age) = retention.warning_max_age().or(retention.max_age()) {
+            while let Some(key) =
+                Self::pop_aged(&mut self.closed_with_warnings, &self.records, max_age, now)
+            {
+                self.remove(key);
+            }
+        }
+        // events are only tracked in `event_times` when there is a max age
+        let Some(max_age) = retention.max_age() else {
+            return;
+        };
+        let warning_max_age = retention.warning_max_age().unwrap_or(max_age);
+        while let Some(&(time, key)) = self.event_times.front() {
+            if now - time <= max_age {
+                break;
+            }
+            self.event_times.pop_front();
+            match self.records.get(&key) {
+                Some(span) if span.has_warnings() && warning_max_age > max_age => {
+                    self.warning_event_times.push_back((time, key));
+                }
+                _ => self.remove_events_before(key, now - max_age),
+            }
+        }
+        while let Some(&(time, key)) = self.warning_event_times.front() {
+            if now - time <= warning_max_age {
+                break;
+            }
+            self.warning_event_times.pop_front();
+            self.remove_events_before(key, now - warning_max_age);
+        }
+    }
+
+    /// Removes the span's events, and event groups without a later event, recorded before
+    /// `cutoff`.
+    fn remove_events_before(&mut self, key: SpanKey, cutoff: DateTime<Local>) {
+        let Some(span) = self.records.get_mut(&key) else {
+            return;
+        };
+        let aged = span.events.front().is_some_and(|event| event.time < cutoff)
+            || span
+                .event_groups
+                .iter()
+                .any(|group| group.last_time < cutoff);
+        if !aged {
+            return;
+        }
+        let span = Arc::make_mut(span);
+        let before = span.event_len();
+        while span.events.front().is_some_and(|event| event.time < cutoff) {
+            span.events.pop_front();
+        }
+        span.event_groups.retain(|group| group.last_time >= cutoff);
+        self.event_count -= before - span.event_len();
+        if span.event_len() == 0 {
+            let open = span.close_time.is_none();
+            self.with_events.remove(&(open, key));
+        }
+    }
+
+    /// Pops the oldest span from `closed` if it closed more than `max_age` ago.
+    fn pop_aged(
+        closed: &mut VecDeque<SpanKey>,
+        records: &BTreeMap<SpanKey, Arc<SpanRecord>>,
+        max_age: Duration,
+        now: DateTime<Local>,
+    ) -> Option<SpanKey> {
+        let close_time = records.get(closed.front()?)?.close_time?;
+        (now - close_time > max_age)
+            .then(|| closed.pop_front())
+            .flatten()
+    }
+
+    /// Removes the oldest closed spans until at most `max` remain, preferring spans without
+    /// warnings.
+    fn remove_oldest_closed(&mut self, max: usize) {
+        while self.closed.len() + self.closed_with_warnings.len() > max {
+            let Some(key) = self
+                .closed
+                .pop_front()
+                .or_else(|| self.closed_with_warnings.pop_front())
+            else {
+                break;
+            };
+            self.remove(key);
+        }
+    }
+
+    /// Removes the oldest events until at most `max` remain, taking events from the oldest closed
+    /// spans first.
+    fn remove_oldest_events(&mut self, max: usize) {
+        while self.event_count > max {
+            let Some(&(open, key)) = self.with_events.first() else {
+                break;
+            };
+            let Some(span) = self.records.get_mut(&key).map(Arc::make_mut) else {
+                self.with_events.remove(&(open, key));
+                continue;
+            };
+            span.pop_event();
+            self.event_count -= 1;
+            if span.event_len() == 0 {
+                self.with_events.remove(&(open, key));
+            }
         }
     }
 }
@@ -85,13 +705,89 @@ pub struct SpanRecord {
     pub level: Level,
     pub name: String,
     pub target: String,
-    pub events: Vec<EventRecord>,
+    /// Where the span is defined in the source code.
+    pub location: Location,
+    /// The thread the span was created on, or `None` for the root span.
+    pub thread: Option<ThreadInfo>,
+    /// The key of the parent span, if any.
+    pub parent: Option<SpanKey>,
+    /// The keys of the child spans, in creation order.
+    pub children: Vec<SpanKey>,
+    /// The span's fields, as recorded on creation and updated by [`Span::record`].
+    ///
+    /// [`Span::record`]: tracing::Span::record
+    pub fields: FieldMap,
+    pub events: VecDeque<EventRecord>,
+    /// Repeated events grouped together, populated instead of `events` when the store has
+    /// [aggregation](TraceStore::with_aggregation) enabled.
+    pub event_groups: Vec<EventGroup>,
+    /// The number of WARN events recorded in the span, including events that are no longer
+    /// stored.
+    pub warn_count: u64,
+    /// The number of ERROR events recorded in the span, including events that are no longer
+    /// stored.
+    pub error_count: u64,
 }
 
 impl SpanRecord {
+    /// Creates a record for a newly created span, capturing the fields in its attributes.
+    ///
+    /// The parent is left unset, as the parent's key depends on the store the record is inserted
+    /// into.
+    pub fn new<'a, R: LookupSpan<'a>>(span: SpanRef<'a, R>, attrs: &Attributes<'_>) -> Self {
+        let fields = FieldMapVisitor::default().visit(attrs);
+        Self {
+            fields,
+            ..Self::from(span)
+        }
+    }
+
+    /// Get the stored event with the given id.
+    pub fn event(&self, id: EventId) -> Option<&EventRecord> {
+        let index = self
+            .events
+            .binary_search_by_key(&id, EventRecord::id)
+            .ok()?;
+        self.events.get(index)
+    }
+
+    /// Get the event group whose first event has the given id, when events are aggregated.
+    pub fn event_group(&self, id: EventId) -> Option<&EventGroup> {
+        let index = self
+            .event_groups
+            .binary_search_by_key(&id, EventGroup::id)
+            .ok()?;
+        self.event_groups.get(index)
+    }
+
+    /// Returns whether the span recorded any WARN or ERROR events.
+    pub fn has_warnings(&self) -> bool {
+        self.warn_count > 0 || self.error_count > 0
+    }
+
     fn close(&mut self) {
         self.close_time = Some(Local::now());
     }
+
+    fn count_event(&mut self, event: &EventRecord) {
+        match event.level.0 {
+            tracing::Level::WARN => self.warn_count += 1,
+            tracing::Level::ERROR => self.error_count += 1,
+            _ => {}
+        }
+    }
+
+    /// The number of stored events, or event groups when events are aggregated.
+    fn event_len(&self) -> usize {
+        self.events.len() + self.event_groups.len()
+    }
+
+    /// Removes the oldest stored event, or event group when events are aggregated.
+    fn pop_event(&mut self) {
+        if self.events.pop_front().is_none() && !self.event_groups.is_empty() {
+            self.event_groups.remove(0);
+        }
+    }
 }
 
 impl<'a, R: LookupSpan<'a>> From<SpanRef<'a, R>> for SpanRecord {
@@ -108,26 +804,88 @@ impl<'a, R: LookupSpan<'a>> From<SpanRef<'a, R>> for SpanRecord {
             level: span.metadata().level().to_owned().into(),
             name: span.metadata().name().to_owned(),
             target: span.metadata().target().to_owned(),
-            events: Vec::new(),
+            location: span.metadata().into(),
+            thread: Some(ThreadInfo::current()),
+            // a span has a different key in each store, so the layer recording it sets the parent
+            parent: None,
+            children: Vec::new(),
+            fields: FieldMap::new(),
+            events: VecDeque::new(),
+            event_groups: Vec::new(),
+            warn_count: 0,
+            error_count: 0,
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct EventRecord {
+    /// The id of the event, assigned when it is inserted into a store.
+    pub(crate) id: EventId,
     pub(crate) time: DateTime<Local>,
     pub(crate) level: Level,
+    pub(crate) target: String,
+    pub(crate) callsite: Identifier,
+    pub(crate) location: Location,
+    pub(crate) thread: ThreadInfo,
     pub(crate) fields: FieldMap,
 }
 
+impl EventRecord {
+    /// Get the id of the event in its store.
+    pub fn id(&self) -> EventId {
+        self.id
+    }
+
+    /// Get the time the event was recorded.
+    pub fn time(&self) -> DateTime<Local> {
+        self.time
+    }
+
+    /// Get the level of the event.
+    pub fn level(&self) -> &Level {
+        &self.level
+    }
+
+    /// Get the target of the event.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// Get where the event is defined in the source code.
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    /// Get the thread the event was recorded on.
+    pub fn thread(&self) -> &ThreadInfo {
+        &self.thread
+    }
+
+    /// Get the fields of the event, including the message.
+    pub fn fields(&self) -> &FieldMap {
+        &self.fields
+    }
+
+    /// Returns the event's message, if it has one.
+    pub fn message(&self) -> Option<&str> {
+        self.fields.get("message").and_then(FieldValue::as_str)
+    }
+}
+
 impl From<&tracing::Event<'_>> for EventRecord {
     fn from(event: &tracing::Event) -> Self {
         let visitor = FieldMapVisitor::default();
         let fields = visitor.visit(&event);
         let metadata = event.metadata();
         EventRecord {
+            id: EventId(0),
             time: Local::now(),
             level: metadata.level().to_owned().into(),
+            target: metadata.target().to_owned(),
+            callsite: metadata.callsite(),
+            location: metadata.into(),
+            thread: ThreadInfo::current(),
             fields,
         }
     }
@@ -142,17 +900,198 @@ impl From<tracing::Level> for Level {
     }
 }
 
-pub(crate) type FieldMap = IndexMap<String, String>;
+/// Where a span or event is defined in the source code, as far as its metadata records it.
+///
+/// Displayed as `file:line`, e.g. `src/db.rs:42`, falling back to the module path when the file
+/// is unknown.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub file: Option<&'static str>,
+    pub line: Option<u32>,
+    pub module_path: Option<&'static str>,
+}
+
+impl Location {
+    /// Returns whether neither the file nor the module path is known.
+    pub fn is_unknown(&self) -> bool {
+        self.file.is_none() && self.module_path.is_none()
+    }
+}
+
+impl From<&'static Metadata<'static>> for Location {
+    fn from(metadata: &'static Metadata<'static>) -> Self {
+        Self {
+            file: metadata.file(),
+            line: metadata.line(),
+            module_path: metadata.module_path(),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.file, self.line, self.module_path) {
+            (Some(file), Some(line), _) => write!(f, "{file}:{line}"),
+            (Some(file), None, _) => f.write_str(file),
+            (None, _, Some(module_path)) => f.write_str(module_path),
+            (None, _, None) => f.write_str("<unknown>"),
+        }
+    }
+}
+
+/// The thread a span or event was recorded on.
+///
+/// Displayed as the thread's name, or its id if it is unnamed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: Option<String>,
+    /// The tokio task running on the thread, which is only recorded with the `tokio` feature.
+    pub task: Option<tokio::task::Id>,
+}
+
+impl ThreadInfo {
+    /// Get the current thread, and the current tokio task with the `tokio` feature.
+    pub fn current() -> Self {
+        let thread = thread::current();
+        Self {
+            id: thread.id(),
+            name: thread.name().map(str::to_owned),
+            task: current_task(),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+fn current_task() -> Option<tokio::task::Id> {
+    tokio::task::try_id()
+}
+
+#[cfg(not(feature = "tokio"))]
+fn current_task() -> Option<tokio::task::Id> {
+    None
+}
+
+impl fmt::Display for ThreadInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{:?}", self.id),
+        }
+    }
+}
+
+pub type FieldMap = IndexMap<String, FieldValue>;
+
+/// The value of a span or event field, keeping the type it was recorded with.
+///
+/// Displayed without quotes or other decoration, so a string field `name = "bob"` displays as
+/// `bob`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Str(String),
+    /// A value recorded with its `Debug` implementation, including messages.
+    Debug(String),
+    /// An error, with the messages of its [sources](std::error::Error::source) from the
+    /// immediate cause to the root cause.
+    Error {
+        message: String,
+        sources: Vec<String>,
+    },
+}
+
+impl FieldValue {
+    /// Returns the value if it is a string, or was recorded with its `Debug` implementation.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            FieldValue::Str(value) | FieldValue::Debug(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a bool.
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            FieldValue::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a float if it is a number.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            FieldValue::I64(value) => Some(value as f64),
+            FieldValue::U64(value) => Some(value as f64),
+            FieldValue::F64(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the value is a number.
+    pub fn is_number(&self) -> bool {
+        self.as_f64().is_some()
+    }
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldValue::Bool(value) => value.fmt(f),
+            FieldValue::I64(value) => value.fmt(f),
+            FieldValue::U64(value) => value.fmt(f),
+            FieldValue::F64(value) => value.fmt(f),
+            FieldValue::Str(value) | FieldValue::Debug(value) => f.write_str(value),
+            FieldValue::Error { message, .. } => f.write_str(message),
+        }
+    }
+}
 
 #[derive(Debug, Default)]
 pub struct FieldMapVisitor {
     fields: FieldMap,
 }
 
+impl FieldMapVisitor {
+    fn insert(&mut self, field: &Field, value: FieldValue) {
+        self.fields.insert(field.name().to_owned(), value);
+    }
+}
+
 impl Visit for FieldMapVisitor {
-    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
-        self.fields
-            .insert(field.name().to_owned(), format!("{:?}", value));
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, FieldValue::Bool(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, FieldValue::I64(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, FieldValue::U64(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, FieldValue::F64(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, FieldValue::Str(value.to_owned()));
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        let sources = iter::successors(value.source(), |error| error.source())
+            .map(ToString::to_string)
+            .collect();
+        let message = value.to_string();
+        self.insert(field, FieldValue::Error { message, sources });
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, FieldValue::Debug(format!("{value:?}")));
     }
 }
 
@@ -161,3 +1100,375 @@ impl VisitOutput<FieldMap> for FieldMapVisitor {
         self.fields
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tracing::subscriber::with_default;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{capture, capture_with, GroupBy, RetentionPolicy, TimingLayer, TracingLayer};
+
+    #[test]
+    fn tree_nests_contextual_and_explicit_children() {
+        let store = capture(|| {
+            let request = tracing::info_span!("request");
+            let _guard = request.enter();
+            let handler = tracing::info_span!("handler");
+            let _db = tracing::info_span!(parent: &handler, "db");
+            let _other = tracing::info_span!(parent: None, "other");
+        });
+        let tree: Vec<_> = store
+            .tree()
+            .into_iter()
+            .map(|(depth, _, span)| (depth, span.name.clone()))
+            .collect();
+        let expected = [
+            (0, "root"),
+            (0, "request"),
+            (1, "handler"),
+            (2, "db"),
+            (0, "other"),
+        ]
+        .map(|(depth, name)| (depth, name.to_owned()));
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn layers_record_into_separate_stores() {
+        let (first, second) = (TraceStore::default(), TraceStore::default());
+        let subscriber = tracing_subscriber::registry()
+            .with(TimingLayer::default())
+            .with(TracingLayer::with_store(first.clone()))
+            .with(TracingLayer::with_store(second.clone()));
+        with_default(subscriber, || {
+            tracing::info_span!("request").in_scope(|| {
+                let _db = tracing::info_span!("db");
+                tracing::info!("handled");
+            });
+        });
+        for store in [first, second] {
+            let tree: Vec<_> = store
+                .tree()
+                .into_iter()
+                .map(|(depth, _, span)| (depth, span.name.clone(), span.events.len()))
+                .collect();
+            let expected = [(0, "root", 0), (0, "request", 1), (1, "db", 0)]
+                .map(|(depth, name, events)| (depth, name.to_owned(), events));
+            assert_eq!(tree, expected);
+        }
+    }
+
+    #[test]
+    fn span_fields_are_recorded() {
+        let store = capture(|| {
+            let span = tracing::info_span!("request", user_id = 42, status = tracing::field::Empty);
+            span.record("status", 200);
+        });
+        let request = &store.spans()[1];
+        assert_eq!(request.fields["user_id"], FieldValue::I64(42));
+        assert_eq!(request.fields["status"], FieldValue::I64(200));
+    }
+
+    #[test]
+    fn fields_keep_their_types() {
+        let error = std::io::Error::other("disk full");
+        let store = capture(|| {
+            tracing::info!(
+                ok = true,
+                delta = -1,
+                count = 2u64,
+                ratio = 0.5,
+                name = "bob",
+                id = ?SpanKey::ROOT,
+                error = &error as &dyn std::error::Error,
+            );
+        });
+        let event = &store.spans()[0].events[0];
+        let fields: Vec<_> = event.fields().values().cloned().collect();
+        assert_eq!(
+            fields,
+            [
+                FieldValue::Bool(true),
+                FieldValue::I64(-1),
+                FieldValue::U64(2),
+                FieldValue::F64(0.5),
+                FieldValue::Str("bob".to_owned()),
+                FieldValue::Debug("SpanKey(0)".to_owned()),
+                FieldValue::Error {
+                    message: "disk full".to_owned(),
+                    sources: Vec::new(),
+                },
+            ]
+        );
+        // events without a message can still be displayed
+        assert_eq!(event.message(), None);
+        assert!(ratatui::text::ToLine::to_line(event)
+            .to_string()
+            .contains("name: bob"));
+    }
+
+    #[test]
+    fn aggregation_groups_repeated_events() {
+        let aggregation = Aggregation::new(GroupBy::Message).with_max_samples(2);
+        let store = capture_with(TraceStore::default().with_aggregation(aggregation), || {
+            for i in 0..3 {
+                tracing::info!(i, "tick");
+            }
+            tracing::info!("tock");
+        });
+        let root = &store.spans()[0];
+        assert!(root.events.is_empty());
+        let groups: Vec<_> = root
+            .event_groups
+            .iter()
+            .map(|group| (group.message(), group.count, group.samples.len()))
+            .collect();
+        assert_eq!(groups, [(Some("tick"), 3, 2), (Some("tock"), 1, 1)]);
+        let samples: Vec<_> = root.event_groups[0]
+            .samples
+            .iter()
+            .map(|event| &event.fields["i"])
+            .collect();
+        assert_eq!(samples, [&FieldValue::I64(1), &FieldValue::I64(2)]);
+    }
+
+    #[test]
+    fn aggregation_by_callsite_ignores_message() {
+        let aggregation = Aggregation::new(GroupBy::Callsite);
+        let store = capture_with(TraceStore::default().with_aggregation(aggregation), || {
+            for i in 0..3 {
+                tracing::info!("tick {i}");
+            }
+        });
+        let root = &store.spans()[0];
+        assert_eq!(root.event_groups.len(), 1);
+        assert_eq!(root.event_groups[0].count, 3);
+    }
+
+    #[test]
+    fn closed_spans_keep_their_records() {
+        let store = capture(|| {
+            for i in 0..100 {
+                drop(tracing::info_span!("span", i));
+            }
+        });
+        let keys: HashSet<SpanKey> = store.tree().into_iter().map(|(_, key, _)| key).collect();
+        assert_eq!(keys.len(), 101);
+    }
+
+    fn span_names(store: &TraceStore) -> Vec<String> {
+        store.spans().iter().map(|span| span.name.clone()).collect()
+    }
+
+    #[test]
+    fn query_filters_spans_and_events() {
+        let store = capture(|| {
+            tracing::info_span!("request", user_id = 42).in_scope(|| {
+                tracing::debug!("received");
+                tracing::warn!("slow");
+            });
+            tracing::info_span!("request", user_id = 7).in_scope(|| tracing::warn!("slow"));
+        });
+
+        let query = TraceQuery::new()
+            .with_min_level(tracing::Level::INFO)
+            .with_field_eq("user_id", "42");
+        let spans = store.query_spans(&query);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].1.fields["user_id"], FieldValue::I64(42));
+        let events = store.query_events(&query);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].1.message(), Some("slow"));
+        assert_eq!(events[0].0, spans[0].0);
+    }
+
+    #[test]
+    fn retention_limits_events_per_span() {
+        let retention = RetentionPolicy::new().with_max_events_per_span(3);
+        let store = capture_with(TraceStore::default().with_retention(retention), || {
+            for i in 0..5 {
+                tracing::info!(i);
+            }
+        });
+        let root = &store.spans()[0];
+        let events: Vec<_> = root
+            .events
+            .iter()
+            .map(|e| e.fields["i"].to_string())
+            .collect();
+        assert_eq!(events, ["2", "3", "4"]);
+    }
+
+    #[test]
+    fn retention_limits_total_events_oldest_span_first() {
+        let retention = RetentionPolicy::new().with_max_events(3);
+        let store = capture_with(TraceStore::default().with_retention(retention), || {
+            tracing::info_span!("first").in_scope(|| {
+                tracing::info!("a");
+                tracing::info!("b");
+            });
+            tracing::info_span!("second").in_scope(|| {
+                tracing::info!("c");
+                tracing::info!("d");
+            });
+        });
+        let spans = store.spans();
+        let events: Vec<_> = spans
+            .iter()
+            .map(|span| {
+                span.events
+                    .iter()
+                    .filter_map(EventRecord::message)
+                    .collect()
+            })
+            .collect::<Vec<Vec<_>>>();
+        assert_eq!(events, [vec![], vec!["b"], vec!["c", "d"]]);
+    }
+
+    #[test]
+    fn retention_takes_events_from_closed_spans_first() {
+        let retention = RetentionPolicy::new().with_max_events(2);
+        let store = capture_with(TraceStore::default().with_retention(retention), || {
+            tracing::info!("open");
+            tracing::info_span!("closed").in_scope(|| tracing::info!("closed"));
+            tracing::info!("last");
+        });
+        let messages: Vec<_> = store.spans()[0]
+            .events
+            .iter()
+            .filter_map(|event| event.message().map(str::to_owned))
+            .collect();
+        assert_eq!(messages, ["open", "last"]);
+        assert!(store.spans()[1].events.is_empty());
+    }
+
+    #[test]
+    fn retention_limits_closed_spans_keeping_warnings() {
+        let retention = RetentionPolicy::new().with_max_closed_spans(2);
+        let store = capture_with(TraceStore::default().with_retention(retention), || {
+            tracing::info_span!("warned").in_scope(|| tracing::warn!("careful"));
+            for name in ["a", "b", "c"] {
+                drop(tracing::info_span!("span", name));
+            }
+        });
+        let names: Vec<_> = store
+            .spans()
+            .into_iter()
+            .map(|span| {
+                span.fields
+                    .get("name")
+                    .map_or(span.name.clone(), ToString::to_string)
+            })
+            .collect();
+        assert_eq!(names, ["root", "warned", "c"]);
+    }
+
+    #[test]
+    fn retention_keeps_spans_with_warnings_longer() {
+        let retention = RetentionPolicy::new()
+            .with_max_age(Duration::milliseconds(-1))
+            .with_warning_max_age(Duration::hours(1));
+        let store = capture_with(TraceStore::default().with_retention(retention), || {
+            tracing::info_span!("warned").in_scope(|| tracing::warn!("careful"));
+            drop(tracing::info_span!("quiet"));
+            // closing a span enforces the policy
+            drop(tracing::info_span!("last"));
+        });
+        assert_eq!(span_names(&store), ["root", "warned"]);
+    }
+
+    #[test]
+    fn retention_ages_out_events_outside_of_spans() {
+        let retention = RetentionPolicy::new().with_max_age(Duration::milliseconds(50));
+        let store = capture_with(TraceStore::default().with_retention(retention), || {
+            tracing::info!("old");
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            tracing::info_span!("open").in_scope(|| tracing::info!("recent"));
+        });
+        let spans = store.spans();
+        let messages = |span: &SpanRecord| {
+            let events = span.events.iter().filter_map(EventRecord::message);
+            events.map(str::to_owned).collect::<Vec<_>>()
+        };
+        assert!(messages(&spans[0]).is_empty());
+        assert_eq!(messages(&spans[1]), ["recent"]);
+    }
+
+    #[test]
+    fn snapshots_are_not_affected_by_later_changes() {
+        let store = TraceStore::default();
+        capture_with(store.clone(), || tracing::info!("first"));
+        let snapshot = store.spans();
+        capture_with(store.clone(), || tracing::info!("second"));
+        assert_eq!(snapshot[0].events.len(), 1);
+        assert_eq!(store.spans()[0].events.len(), 2);
+        let count: usize = store.with_spans(|spans| spans.map(|(_, span)| span.events.len()).sum());
+        assert_eq!(count, 2);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn events_record_the_task_they_are_in() {
+        let task = tokio::spawn(async { capture(|| tracing::info!("in a task")) });
+        let id = task.id();
+        let store = task.await.unwrap();
+        let root = store.get(SpanKey::ROOT).unwrap();
+        assert_eq!(root.events[0].thread().task, Some(id));
+    }
+
+    #[tokio::test]
+    async fn subscribers_are_notified_of_changes() {
+        let store = TraceStore::default();
+        let mut subscription = store.subscribe();
+        let generation = store.generation();
+        capture_with(store.clone(), || tracing::info!("changed"));
+        assert!(store.generation() > generation);
+        assert_eq!(subscription.changed().await, store.generation());
+    }
+
+    #[test]
+    fn generation_is_unchanged_when_nothing_changes() {
+        let store = TraceStore::default();
+        let generation = store.generation();
+        store.record_fields(SpanKey(42), FieldMap::new());
+        store.close_span(SpanKey(42));
+        store.remove_expired(Duration::zero());
+        assert_eq!(store.generation(), generation);
+    }
+
+    #[test]
+    fn remove_expired_unlinks_children() {
+        let store = capture(|| {
+            let parent = tracing::info_span!("parent");
+            drop(tracing::info_span!(parent: &parent, "child"));
+            // keep the parent open so that only the child expires
+            std::mem::forget(parent);
+        });
+        let parent = store.roots()[1];
+        assert_eq!(store.children(parent).len(), 1);
+        store.remove_expired(Duration::milliseconds(-1));
+        assert_eq!(store.roots(), [SpanKey::ROOT, parent]);
+        assert!(store.children(parent).is_empty());
+    }
+
+    #[test]
+    fn span_stats_survive_removal() {
+        let store = capture(|| {
+            for _ in 0..3 {
+                tracing::info_span!("query").in_scope(|| tracing::error!("failed"));
+            }
+            drop(tracing::info_span!("connect"));
+        });
+        store.remove_expired(Duration::milliseconds(-1));
+        assert_eq!(span_names(&store), ["root"]);
+
+        let stats = store.span_stats();
+        let names: Vec<_> = stats.iter().map(|stats| stats.name()).collect();
+        assert_eq!(names, ["query", "connect"]);
+        let query = store.span_stats_for(module_path!(), "query").unwrap();
+        assert_eq!(query.count(), 3);
+        assert_eq!(query.error_events(), 3);
+        assert!(query.total_quantile(0.5) <= query.total_max());
+    }
+}
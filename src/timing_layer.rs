@@ -1,6 +1,12 @@
-use std::time::Duration;
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    thread::{self, ThreadId},
+    time::Duration,
+};
 
 use quanta::Instant;
+use thread_local::ThreadLocal;
 use tracing::{
     span::{self, Attributes},
     Subscriber,
@@ -13,20 +19,87 @@ use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
 /// extensions. The layer records the time spent in each span as either "idle" time, when the
 /// span is not executing, or "busy" time, when the span is executing. The layer records the
 /// time spent in each span as a [`Timing`] resource, which can be accessed by other layers.
+///
+/// Busy time includes the time spent in child spans entered while the span is entered. The layer
+/// keeps a stack of the spans entered through it on each thread to also record the busy time spent
+/// in the span itself, excluding its children (see [`Timing::self_busy_duration`]).
+/// A span entered on top of another one counts as its child, whether or not it is its parent.
+///
+/// By default only the total idle and busy time is recorded. Use
+/// [`with_max_intervals`](Self::with_max_intervals) to also record when each span was busy.
+///
+/// Each time a span is entered and exited counts as a poll, as when an instrumented future is
+/// polled. Use [`with_long_poll_threshold`](Self::with_long_poll_threshold) to count the polls
+/// that block for too long, e.g. because of blocking IO inside an async task.
 #[derive(Debug, Default)]
-pub struct TimingLayer;
+pub struct TimingLayer {
+    max_intervals: Option<usize>,
+    long_poll_threshold: Option<Duration>,
+    /// The spans entered on each thread, innermost last.
+    entered: ThreadLocal<RefCell<Vec<span::Id>>>,
+}
+
+/// The span a span was entered on top of, stored in the span's extensions.
+///
+/// This is the span whose [`Timing`] counts the span as an entered child, which is not
+/// necessarily its parent, e.g. for a span created with `parent: None`.
+#[derive(Debug)]
+pub(crate) struct EnteredOnTopOf(pub(crate) Option<span::Id>);
 
 /// A resource tracking the idle and busy time spent in each span.
 ///
 /// This is used by the [`TimingLayer`] to track the time spent in each span.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Timing {
     state: State,
     idle: Duration,
     busy: Duration,
+    self_busy: Duration,
+    /// The number of child spans entered on top of this span.
+    children_entered: u32,
+    created: Instant,
     last: Instant,
     enter_count: u64,
     exit_count: u64,
+    intervals: Option<IntervalLog>,
+    /// The self busy time when the current poll started, if the span is busy.
+    poll_start: Option<Duration>,
+    longest_poll: Duration,
+    long_poll_threshold: Option<Duration>,
+    long_polls: u64,
+    /// The thread the span was last entered on.
+    thread: ThreadId,
+}
+
+/// A bounded log of the intervals during which a span was busy.
+///
+/// Once the log is full, the oldest intervals are removed and added to a summary of the dropped
+/// intervals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntervalLog {
+    intervals: VecDeque<Interval>,
+    capacity: usize,
+    dropped: DroppedIntervals,
+}
+
+/// An interval during which a span was busy, relative to when the span was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub start: Duration,
+    pub end: Duration,
+    /// The thread the span was entered on.
+    pub thread: ThreadId,
+}
+
+/// A summary of the intervals removed from a full [`IntervalLog`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DroppedIntervals {
+    /// The number of intervals removed.
+    pub count: u64,
+    /// The total busy time of the removed intervals.
+    pub busy: Duration,
+    /// The end of the last removed interval, relative to when the span was created.
+    pub end: Duration,
 }
 
 impl Default for Timing {
@@ -60,7 +133,14 @@ where
     fn on_new_span(&self, _attrs: &Attributes<'_>, id: &span::Id, ctx: Context<'_, C>) {
         let span = ctx.span(id).expect("span not found");
         let mut extensions = span.extensions_mut();
-        extensions.insert(Timing::new());
+        let mut timing = Timing::new();
+        if let Some(max_intervals) = self.max_intervals {
+            timing = timing.with_max_intervals(max_intervals);
+        }
+        if let Some(threshold) = self.long_poll_threshold {
+            timing = timing.with_long_poll_threshold(threshold);
+        }
+        extensions.insert(timing);
     }
 
     /// Records that a span has been entered.
@@ -68,10 +148,20 @@ where
     /// The subscriber records the time spent in the span as "idle" time, as the span is not
     /// executing.
     fn on_enter(&self, id: &span::Id, ctx: Context<'_, C>) {
+        let mut entered = self.entered.get_or_default().borrow_mut();
+        let parent = entered.last().cloned();
+        entered.push(id.clone());
+        drop(entered);
+        if let Some(parent) = parent.as_ref().and_then(|parent| ctx.span(parent)) {
+            if let Some(timings) = parent.extensions_mut().get_mut::<Timing>() {
+                timings.enter_child();
+            }
+        }
         let span = ctx.span(id).expect("span not found");
         let mut extensions = span.extensions_mut();
         let timings = extensions.get_mut::<Timing>().expect("timings not found");
         timings.enter();
+        extensions.replace(EnteredOnTopOf(parent));
     }
 
     /// Records that a span has been exited.
@@ -82,6 +172,23 @@ where
         let mut extensions = span.extensions_mut();
         let timings = extensions.get_mut::<Timing>().expect("timings not found");
         timings.exit();
+        drop(extensions);
+
+        let mut entered = self.entered.get_or_default().borrow_mut();
+        let parent = entered
+            .iter()
+            .rposition(|entered| entered == id)
+            .and_then(|index| {
+                entered.remove(index);
+                index.checked_sub(1).map(|parent| entered[parent].clone())
+            });
+        drop(entered);
+        if let Some(parent) = parent.as_ref().and_then(|parent| ctx.span(parent)) {
+            if let Some(timings) = parent.extensions_mut().get_mut::<Timing>() {
+                timings.exit_child();
+            }
+        }
+        span.extensions_mut().replace(EnteredOnTopOf(parent));
     }
 
     /// Records that a span has been closed.
@@ -96,27 +203,76 @@ where
     }
 }
 
+impl TimingLayer {
+    /// Create a layer that records only the total idle and busy time of each span.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record up to this many busy intervals per span, in addition to the totals.
+    ///
+    /// See [`Timing::intervals`].
+    pub fn with_max_intervals(mut self, max_intervals: usize) -> Self {
+        self.max_intervals = Some(max_intervals);
+        self
+    }
+
+    /// Count the polls of each span that are busy for longer than the threshold.
+    ///
+    /// See [`Timing::long_poll_count`].
+    pub fn with_long_poll_threshold(mut self, threshold: Duration) -> Self {
+        self.long_poll_threshold = Some(threshold);
+        self
+    }
+}
+
 impl Timing {
     /// Create a new `Timing` resource.
     pub fn new() -> Self {
+        let now = Instant::now();
         Self {
             state: State::Idle,
             idle: Duration::ZERO,
             busy: Duration::ZERO,
-            last: Instant::now(),
+            self_busy: Duration::ZERO,
+            children_entered: 0,
+            created: now,
+            last: now,
             enter_count: 0,
             exit_count: 0,
+            intervals: None,
+            poll_start: None,
+            longest_poll: Duration::ZERO,
+            long_poll_threshold: None,
+            long_polls: 0,
+            thread: thread::current().id(),
         }
     }
 
+    /// Record up to this many busy intervals, in addition to the totals.
+    pub fn with_max_intervals(mut self, max_intervals: usize) -> Self {
+        self.intervals = Some(IntervalLog::new(max_intervals));
+        self
+    }
+
+    /// Count the polls that are busy for longer than the threshold.
+    pub fn with_long_poll_threshold(mut self, threshold: Duration) -> Self {
+        self.long_poll_threshold = Some(threshold);
+        self
+    }
+
     /// Record that the span is active.
     ///
     /// If this is called while the span is idle, the idle time will be updated. If this is called
     /// while the span is busy, the busy time will be updated.
     pub fn enter(&mut self) {
         self.record();
+        if self.state != State::Busy {
+            self.poll_start = Some(self.self_busy);
+        }
         self.enter_count += 1;
         self.state = State::Busy;
+        self.thread = thread::current().id();
     }
 
     /// Record that the span is idle.
@@ -125,10 +281,25 @@ impl Timing {
     /// while the span is idle, the idle time will be updated.
     pub fn exit(&mut self) {
         self.record();
+        self.finish_poll();
         self.exit_count += 1;
         self.state = State::Idle;
     }
 
+    /// Record that a child span has been entered while this span is entered.
+    ///
+    /// Busy time is not counted as self busy time until the child is exited.
+    pub fn enter_child(&mut self) {
+        self.record();
+        self.children_entered += 1;
+    }
+
+    /// Record that a child span entered while this span is entered has been exited.
+    pub fn exit_child(&mut self) {
+        self.record();
+        self.children_entered = self.children_entered.saturating_sub(1);
+    }
+
     /// Record that the span has been closed.
     ///
     /// If this is called while the span is idle, the idle time will be updated. If this is called
@@ -137,14 +308,44 @@ impl Timing {
     /// After this is called, no further timing information will be recorded.
     fn close(&mut self) {
         self.record();
+        self.finish_poll();
         self.state = State::Closed;
     }
 
+    fn finish_poll(&mut self) {
+        let Some(start) = self.poll_start.take() else {
+            return;
+        };
+        let poll = self.self_busy - start;
+        self.longest_poll = self.longest_poll.max(poll);
+        if self
+            .long_poll_threshold
+            .is_some_and(|threshold| poll > threshold)
+        {
+            self.long_polls += 1;
+        }
+    }
+
     fn record(&mut self) {
-        let now = Instant::now();
+        self.record_at(Instant::now());
+    }
+
+    fn record_at(&mut self, now: Instant) {
         match self.state {
             State::Idle => self.idle += now.duration_since(self.last),
-            State::Busy => self.busy += now.duration_since(self.last),
+            State::Busy => {
+                self.busy += now.duration_since(self.last);
+                if self.children_entered == 0 {
+                    self.self_busy += now.duration_since(self.last);
+                }
+                if let Some(intervals) = &mut self.intervals {
+                    intervals.push(Interval {
+                        start: self.last.duration_since(self.created),
+                        end: now.duration_since(self.created),
+                        thread: self.thread,
+                    });
+                }
+            }
             State::Closed => {}
         }
         self.last = now;
@@ -155,6 +356,64 @@ impl Timing {
         self.state
     }
 
+    /// Returns a copy of the timing with the time since the last state change recorded as of
+    /// `now`, as if the span had changed state at that time.
+    ///
+    /// The durations of a `Timing` are only updated when the span is entered, exited or closed, so
+    /// a span that has been busy for a long time does not show it until it exits. Use this (or the
+    /// `*_at` methods) to include the time spent in the current state.
+    pub fn snapshot(&self, now: Instant) -> Timing {
+        let mut timing = self.clone();
+        timing.record_at(now);
+        timing
+    }
+
+    /// Get the idle time spent in this span, including the current idle time as of `now`.
+    pub fn idle_duration_at(&self, now: Instant) -> Duration {
+        match self.state {
+            State::Idle => self.idle + now.saturating_duration_since(self.last),
+            _ => self.idle,
+        }
+    }
+
+    /// Get the busy time spent in this span, including the current busy time as of `now`.
+    pub fn busy_duration_at(&self, now: Instant) -> Duration {
+        match self.state {
+            State::Busy => self.busy + now.saturating_duration_since(self.last),
+            _ => self.busy,
+        }
+    }
+
+    /// Get the self busy time spent in this span, including the current busy time as of `now`.
+    pub fn self_busy_duration_at(&self, now: Instant) -> Duration {
+        match self.state {
+            State::Busy if self.children_entered == 0 => {
+                self.self_busy + now.saturating_duration_since(self.last)
+            }
+            _ => self.self_busy,
+        }
+    }
+
+    /// Get the total time spent in this span, including the time in the current state as of
+    /// `now`.
+    pub fn total_duration_at(&self, now: Instant) -> Duration {
+        self.idle_duration_at(now) + self.busy_duration_at(now)
+    }
+
+    /// Get the self busy time of the current poll as of `now`, or `None` if the span is not busy.
+    pub fn current_poll_at(&self, now: Instant) -> Option<Duration> {
+        let start = self.poll_start.filter(|_| self.state == State::Busy)?;
+        Some(self.self_busy_duration_at(now) - start)
+    }
+
+    /// Returns whether the current poll has been busy for longer than the long poll threshold as
+    /// of `now`.
+    pub fn is_stuck_at(&self, now: Instant) -> bool {
+        self.current_poll_at(now)
+            .zip(self.long_poll_threshold)
+            .is_some_and(|(poll, threshold)| poll > threshold)
+    }
+
     /// Get the idle time spent in this span.
     pub fn idle_duration(&self) -> Duration {
         self.idle
@@ -165,6 +424,12 @@ impl Timing {
         self.busy
     }
 
+    /// Get the busy time spent in this span, excluding the time during which a child span was
+    /// entered on the same thread.
+    pub fn self_busy_duration(&self) -> Duration {
+        self.self_busy
+    }
+
     /// Get the total time spent in this span.
     pub fn total_duration(&self) -> Duration {
         self.idle + self.busy
@@ -181,6 +446,85 @@ impl Timing {
     pub fn exit_count(&self) -> u64 {
         self.exit_count
     }
+
+    /// Get the longest self busy time of a single poll, from when the span was entered until it
+    /// was exited.
+    ///
+    /// Time spent in child spans is excluded, so that a blocking poll is attributed to the span
+    /// that actually blocked rather than to every span it was polled from.
+    pub fn longest_poll(&self) -> Duration {
+        self.longest_poll
+    }
+
+    /// Get the number of polls with a self busy time longer than the long poll threshold.
+    ///
+    /// This is always 0 if no threshold was set.
+    pub fn long_poll_count(&self) -> u64 {
+        self.long_polls
+    }
+
+    /// Get the intervals during which this span was busy, if they are being recorded.
+    ///
+    /// Intervals are only recorded when the span becomes idle or closed, so an interval that is
+    /// still in progress is not included.
+    pub fn intervals(&self) -> Option<&IntervalLog> {
+        self.intervals.as_ref()
+    }
+}
+
+impl IntervalLog {
+    fn new(capacity: usize) -> Self {
+        Self {
+            intervals: VecDeque::new(),
+            capacity,
+            dropped: DroppedIntervals::default(),
+        }
+    }
+
+    /// Adds an interval, merging it with the previous interval if they are adjacent and on the
+    /// same thread.
+    fn push(&mut self, interval: Interval) {
+        if let Some(last) = self.intervals.back_mut() {
+            if last.end == interval.start && last.thread == interval.thread {
+                last.end = interval.end;
+                return;
+            }
+        }
+        self.intervals.push_back(interval);
+        while self.intervals.len() > self.capacity {
+            let Some(dropped) = self.intervals.pop_front() else {
+                break;
+            };
+            self.dropped.count += 1;
+            self.dropped.busy += dropped.end - dropped.start;
+            self.dropped.end = dropped.end;
+        }
+    }
+
+    /// Returns the recorded intervals, oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Interval> + ExactSizeIterator {
+        self.intervals.iter()
+    }
+
+    /// Get the number of recorded intervals.
+    pub fn len(&self) -> usize {
+        self.intervals.len()
+    }
+
+    /// Returns whether no intervals have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    /// Get the maximum number of intervals kept.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get a summary of the intervals removed because the log was full.
+    pub fn dropped(&self) -> DroppedIntervals {
+        self.dropped
+    }
 }
 
 #[cfg(test)]
@@ -379,6 +723,225 @@ mod tests {
         });
     }
 
+    #[test]
+    fn timing_intervals() {
+        let (clock, mock) = Clock::mock();
+        quanta::with_clock(&clock, || {
+            let mut timing = Timing::new().with_max_intervals(2);
+            assert!(Timing::new().intervals().is_none());
+            for _ in 0..3 {
+                mock.increment(Duration::from_secs(1));
+                timing.enter();
+                mock.increment(Duration::from_secs(2));
+                timing.exit();
+            }
+            let intervals = timing.intervals().unwrap();
+            let secs = |secs| Duration::from_secs(secs);
+            assert_eq!(
+                intervals.iter().copied().collect::<Vec<_>>(),
+                [
+                    Interval {
+                        start: secs(4),
+                        end: secs(6),
+                        thread: thread::current().id(),
+                    },
+                    Interval {
+                        start: secs(7),
+                        end: secs(9),
+                        thread: thread::current().id(),
+                    },
+                ]
+            );
+            assert_eq!(
+                intervals.dropped(),
+                DroppedIntervals {
+                    count: 1,
+                    busy: secs(2),
+                    end: secs(3),
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn timing_intervals_merge_reentrant_enters() {
+        let (clock, mock) = Clock::mock();
+        quanta::with_clock(&clock, || {
+            let mut timing = Timing::new().with_max_intervals(10);
+            timing.enter();
+            mock.increment(Duration::from_secs(1));
+            timing.enter();
+            mock.increment(Duration::from_secs(1));
+            timing.exit();
+            let intervals: Vec<_> = timing.intervals().unwrap().iter().copied().collect();
+            assert_eq!(
+                intervals,
+                [Interval {
+                    start: Duration::ZERO,
+                    end: Duration::from_secs(2),
+                    thread: thread::current().id(),
+                }]
+            );
+        });
+    }
+
+    #[test]
+    fn timing_self_busy_excludes_children() {
+        let (clock, mock) = Clock::mock();
+        quanta::with_clock(&clock, || {
+            let mut timing = Timing::new();
+            timing.enter();
+            mock.increment(Duration::from_secs(1));
+            timing.enter_child();
+            mock.increment(Duration::from_secs(2));
+            timing.exit_child();
+            mock.increment(Duration::from_secs(3));
+            timing.exit();
+            assert_eq!(timing.busy_duration(), Duration::from_secs(6));
+            assert_eq!(timing.self_busy_duration(), Duration::from_secs(4));
+        });
+    }
+
+    #[test]
+    fn timing_long_polls() {
+        let (clock, mock) = Clock::mock();
+        quanta::with_clock(&clock, || {
+            let mut timing = Timing::new().with_long_poll_threshold(Duration::from_millis(10));
+            for millis in [5, 50, 20] {
+                timing.enter();
+                mock.increment(Duration::from_millis(millis));
+                timing.exit();
+            }
+            // a long wait in a child span is not a long poll of the parent
+            timing.enter();
+            timing.enter_child();
+            mock.increment(Duration::from_millis(100));
+            timing.exit_child();
+            timing.exit();
+            assert_eq!(timing.longest_poll(), Duration::from_millis(50));
+            assert_eq!(timing.long_poll_count(), 2);
+        });
+    }
+
+    #[test]
+    fn timing_at_includes_current_state() {
+        let (clock, mock) = Clock::mock();
+        quanta::with_clock(&clock, || {
+            let mut timing = Timing::new().with_long_poll_threshold(Duration::from_secs(10));
+            mock.increment(Duration::from_secs(1));
+            timing.enter();
+            mock.increment(Duration::from_secs(30));
+            let now = Instant::now();
+            assert_eq!(timing.busy_duration(), Duration::ZERO);
+            assert_eq!(timing.busy_duration_at(now), Duration::from_secs(30));
+            assert_eq!(timing.idle_duration_at(now), Duration::from_secs(1));
+            assert_eq!(timing.total_duration_at(now), Duration::from_secs(31));
+            assert_eq!(timing.current_poll_at(now), Some(Duration::from_secs(30)));
+            assert!(timing.is_stuck_at(now));
+
+            let snapshot = timing.snapshot(now);
+            assert_eq!(snapshot.state(), State::Busy);
+            assert_eq!(snapshot.busy_duration(), Duration::from_secs(30));
+            assert_eq!(snapshot.self_busy_duration(), Duration::from_secs(30));
+        });
+    }
+
+    #[test]
+    fn layer_tracks_entered_children() {
+        let (clock, mock) = Clock::mock();
+        let store = quanta::with_clock(&clock, || {
+            crate::capture(|| {
+                tracing::info_span!("parent").in_scope(|| {
+                    mock.increment(Duration::from_secs(1));
+                    tracing::info_span!("child").in_scope(|| {
+                        mock.increment(Duration::from_secs(2));
+                    });
+                });
+            })
+        });
+        let spans = store.spans();
+        let parent = &spans[1].timing;
+        assert_eq!(parent.busy_duration(), Duration::from_secs(3));
+        assert_eq!(parent.self_busy_duration(), Duration::from_secs(1));
+        let child = &spans[2].timing;
+        assert_eq!(child.self_busy_duration(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn parent_is_not_stuck_while_only_its_child_is_busy() {
+        use tracing::subscriber::with_default;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let (layer, store) = crate::TracingLayer::new();
+        let subscriber = tracing_subscriber::registry()
+            .with(TimingLayer::new().with_long_poll_threshold(Duration::from_secs(1)))
+            .with(layer);
+        let (clock, mock) = Clock::mock();
+        quanta::with_clock(&clock, || {
+            with_default(subscriber, || {
+                tracing::info_span!("parent").in_scope(|| {
+                    tracing::info_span!("child").in_scope(|| {
+                        mock.increment(Duration::from_secs(2));
+                        let spans = store.spans();
+                        let now = Instant::now();
+                        assert!(!spans[1].timing.is_stuck_at(now));
+                        assert!(spans[2].timing.is_stuck_at(now));
+                    });
+                });
+            });
+        });
+    }
+
+    #[test]
+    fn span_without_a_parent_is_a_child_of_the_span_it_is_entered_on() {
+        use tracing::subscriber::with_default;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let (layer, store) = crate::TracingLayer::new();
+        let subscriber = tracing_subscriber::registry()
+            .with(TimingLayer::new().with_long_poll_threshold(Duration::from_secs(1)))
+            .with(layer);
+        let (clock, mock) = Clock::mock();
+        quanta::with_clock(&clock, || {
+            with_default(subscriber, || {
+                tracing::info_span!("outer").in_scope(|| {
+                    tracing::info_span!(parent: None, "other").in_scope(|| {
+                        mock.increment(Duration::from_secs(2));
+                        let spans = store.spans();
+                        let now = Instant::now();
+                        assert!(!spans[1].timing.is_stuck_at(now));
+                        assert!(spans[2].timing.is_stuck_at(now));
+                    });
+                });
+            });
+        });
+        let spans = store.spans();
+        assert_eq!(spans[1].timing.self_busy_duration(), Duration::ZERO);
+        assert_eq!(spans[2].timing.self_busy_duration(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn entered_spans_are_tracked_per_subscriber() {
+        let (clock, mock) = Clock::mock();
+        let (outer, inner) = quanta::with_clock(&clock, || {
+            let mut inner = None;
+            let outer = crate::capture(|| {
+                tracing::info_span!("outer").in_scope(|| {
+                    inner = Some(crate::capture(|| {
+                        tracing::info_span!("inner").in_scope(|| {
+                            mock.increment(Duration::from_secs(2));
+                        });
+                    }));
+                });
+            });
+            (outer, inner.unwrap())
+        });
+        let outer = &outer.spans()[1].timing;
+        assert_eq!(outer.self_busy_duration(), Duration::from_secs(2));
+        let inner = &inner.spans()[1].timing;
+        assert_eq!(inner.self_busy_duration(), Duration::from_secs(2));
+    }
+
     #[test]
     fn timing_close_while_closed() {
         let (clock, mock) = Clock::mock();
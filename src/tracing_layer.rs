@@ -1,32 +1,78 @@
 use tracing::{span, Subscriber};
-use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+use tracing_subscriber::{
+    field::VisitOutput,
+    layer::Context,
+    registry::{LookupSpan, SpanRef},
+    Layer,
+};
 
-use crate::{storage::TraceStore, Timing};
+use crate::{
+    storage::{FieldMapVisitor, SpanKey, SpanRecord, TraceStore},
+    timing_layer::EnteredOnTopOf,
+    Timing,
+};
 
 #[derive(Debug, Default)]
 pub struct TracingLayer {
     records: TraceStore,
 }
 
+/// The key of a span in each [`TraceStore`] recording it, stored in the span's extensions so that
+/// several layers can record the same spans into different stores.
+#[derive(Debug, Default)]
+struct SpanKeys(Vec<(usize, SpanKey)>);
+
 impl TracingLayer {
     pub fn new() -> (Self, TraceStore) {
         let records = TraceStore::default();
-        (
-            Self {
-                records: records.clone(),
-            },
-            records.clone(),
-        )
+        (Self::with_store(records.clone()), records)
+    }
+
+    /// Create a layer that records into an existing, pre-configured store.
+    pub fn with_store(records: TraceStore) -> Self {
+        Self { records }
+    }
+
+    /// Copies the timing of the span into its record.
+    fn update_timing<'a, R: LookupSpan<'a>>(&self, span: &SpanRef<'a, R>) {
+        let key = self.key(span);
+        let extensions = span.extensions();
+        let timing = extensions.get::<Timing>().expect("timing not found");
+        self.records.update_timing(key, timing);
     }
 
-    fn update_timing<S>(&self, ctx: Context<S>, id: &span::Id)
+    /// Copies the timing of the span and of the span it was entered on top of, which counts its
+    /// entered children, into their records.
+    fn update_entered_timing<S>(&self, ctx: Context<S>, id: &span::Id)
     where
         S: Subscriber + for<'lookup> LookupSpan<'lookup>,
     {
         let span = ctx.span(id).expect("span not found");
-        let extensions = span.extensions();
-        let timing = extensions.get::<Timing>().expect("timing not found");
-        self.records.update_timing(id.into_u64(), timing);
+        self.update_timing(&span);
+        let below = span
+            .extensions()
+            .get::<EnteredOnTopOf>()
+            .and_then(|below| below.0.clone());
+        if let Some(below) = below.and_then(|below| ctx.span(&below)) {
+            self.update_timing(&below);
+        }
+    }
+
+    fn span_key<S>(&self, ctx: &Context<S>, id: &span::Id) -> SpanKey
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    {
+        self.key(&ctx.span(id).expect("span not found"))
+    }
+
+    /// Get the key of the span in this layer's store.
+    fn key<'a, R: LookupSpan<'a>>(&self, span: &SpanRef<'a, R>) -> SpanKey {
+        let store = self.records.id();
+        span.extensions()
+            .get::<SpanKeys>()
+            .and_then(|keys| keys.0.iter().find(|(id, _)| *id == store))
+            .map(|&(_, key)| key)
+            .expect("span key not found")
     }
 }
 
@@ -34,25 +80,48 @@ impl<S> Layer<S> for TracingLayer
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
 {
-    fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
         let span = ctx.span(id).expect("span not found");
-        self.records.insert_span(id.into_u64(), span.into());
+        let key = self.records.next_key();
+        let parent = span.parent().map(|parent| self.key(&parent));
+        {
+            let mut extensions = span.extensions_mut();
+            let entry = (self.records.id(), key);
+            match extensions.get_mut::<SpanKeys>() {
+                Some(keys) => keys.0.push(entry),
+                None => extensions.insert(SpanKeys(vec![entry])),
+            }
+        }
+        let record = SpanRecord {
+            parent,
+            ..SpanRecord::new(span, attrs)
+        };
+        self.records.insert_span(key, record);
     }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let fields = FieldMapVisitor::default().visit(values);
+        self.records.record_fields(self.span_key(&ctx, id), fields);
+    }
+
     fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
-        self.update_timing(ctx, id);
+        self.update_entered_timing(ctx, id);
     }
 
     fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
-        self.update_timing(ctx, id);
+        self.update_entered_timing(ctx, id);
     }
 
     fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
-        self.update_timing(ctx, &id);
-        self.records.close_span(id.into_u64());
+        let span = ctx.span(&id).expect("span not found");
+        self.update_timing(&span);
+        self.records.close_span(self.key(&span));
     }
 
     fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
-        let id = ctx.event_span(event).map_or(0, |span| span.id().into_u64());
-        self.records.insert_event(id, event.into());
+        let key = ctx
+            .event_span(event)
+            .map_or(SpanKey::ROOT, |span| self.key(&span));
+        self.records.insert_event(key, event.into());
     }
 }
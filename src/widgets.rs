@@ -0,0 +1,33 @@
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    widgets::{Block, Widget},
+};
+
+mod event_groups;
+mod event_list;
+mod search_bar;
+mod span_detail;
+mod span_stats;
+mod timeline;
+mod trace_tree;
+
+pub use event_groups::{EventGroupList, EventGroupListState};
+pub use event_list::{EventListState, EventListWidget};
+pub use search_bar::{SearchBar, SearchBarState};
+pub use span_detail::{SpanDetailState, SpanDetailWidget};
+pub use span_stats::{SpanStatsTable, SpanStatsTableState, StatsColumn};
+pub use timeline::{TimelineState, TimelineWidget};
+pub use trace_tree::{TraceTreeState, TraceTreeWidget, TreeItem};
+
+/// Renders the widget's block, if any, and returns the area inside it.
+fn render_block(block: Option<Block>, area: Rect, buf: &mut Buffer) -> Rect {
+    match block {
+        Some(block) => {
+            let inner = block.inner(area);
+            block.render(area, buf);
+            inner
+        }
+        None => area,
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::Modifier,
+    text::{Line, ToLine, ToText},
+    widgets::{Block, StatefulWidget, Widget},
+};
+
+use crate::aggregation::{EventGroup, GroupKey};
+
+/// A list of [`EventGroup`]s where each group can be expanded to show its sampled events.
+#[derive(Debug, Default, Clone)]
+pub struct EventGroupList<'a> {
+    groups: &'a [EventGroup],
+    block: Option<Block<'a>>,
+}
+
+/// The state of an [`EventGroupList`].
+///
+/// Tracks the selected group, which groups are expanded and the scroll offset. Groups stay
+/// expanded when older groups are removed by the retention policy.
+#[derive(Debug, Default, Clone)]
+pub struct EventGroupListState {
+    selected: Option<usize>,
+    expanded: HashSet<GroupKey>,
+    offset: usize,
+    /// The groups from the last render, used to find the selected group between renders.
+    keys: Vec<GroupKey>,
+}
+
+impl<'a> EventGroupList<'a> {
+    pub fn new(groups: &'a [EventGroup]) -> Self {
+        Self {
+            groups,
+            block: None,
+        }
+    }
+
+    /// Wrap the list in a block.
+    pub fn block(mut self, block: Block<'a>) -> Self {
+        self.block = Some(block);
+        self
+    }
+}
+
+impl EventGroupListState {
+    /// Get the index of the selected group.
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// Select the group at the given index.
+    pub fn select(&mut self, index: Option<usize>) {
+        self.selected = index;
+    }
+
+    /// Select the next group, or the first group if none is selected.
+    pub fn select_next(&mut self) {
+        self.selected = Some(self.selected.map_or(0, |i| i.saturating_add(1)));
+    }
+
+    /// Select the previous group, or the first group if none is selected.
+    pub fn select_previous(&mut self) {
+        self.selected = Some(self.selected.map_or(0, |i| i.saturating_sub(1)));
+    }
+
+    /// Expand the selected group if it is collapsed, or collapse it if it is expanded.
+    pub fn toggle_selected(&mut self) {
+        let Some(key) = self.selected.and_then(|selected| self.keys.get(selected)) else {
+            return;
+        };
+        if !self.expanded.remove(key) {
+            self.expanded.insert(key.clone());
+        }
+    }
+
+    /// Returns whether the group is expanded.
+    pub fn is_expanded(&self, group: &EventGroup) -> bool {
+        self.expanded.contains(group.key())
+    }
+
+    /// Get the index of the first visible line.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl StatefulWidget for EventGroupList<'_> {
+    type State = EventGroupListState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let area = super::render_block(self.block, area, buf);
+        state.selected = state
+            .selected
+            .map(|i| i.min(self.groups.len().saturating_sub(1)));
+        state.keys = self
+            .groups
+            .iter()
+            .map(|group| group.key().clone())
+            .collect();
+
+        let mut lines: Vec<Line> = Vec::new();
+        let mut selected_line = None;
+        for (index, group) in self.groups.iter().enumerate() {
+            let expanded = state.is_expanded(group);
+            let mut group_lines = if expanded {
+                group.to_text().lines
+            } else {
+                vec![group.to_line()]
+            };
+            let marker = if expanded { "▾ " } else { "▸ " };
+            group_lines[0].spans.insert(0, marker.into());
+            if state.selected == Some(index) {
+                selected_line = Some(lines.len());
+                let line = std::mem::take(&mut group_lines[0]);
+                group_lines[0] = line.patch_style(Modifier::REVERSED);
+            }
+            lines.extend(group_lines);
+        }
+
+        let height = area.height as usize;
+        if let Some(selected_line) = selected_line {
+            if selected_line < state.offset {
+                state.offset = selected_line;
+            } else if selected_line >= state.offset + height {
+                state.offset = selected_line + 1 - height;
+            }
+        }
+        state.offset = state.offset.min(lines.len().saturating_sub(height));
+
+        for (line, row) in lines.into_iter().skip(state.offset).zip(area.rows()) {
+            line.render(row, buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture_with, Aggregation, GroupBy, TraceStore};
+
+    #[test]
+    fn expanded_groups_survive_earlier_groups_being_removed() {
+        let store = TraceStore::default().with_aggregation(Aggregation::new(GroupBy::Message));
+        let store = capture_with(store, || {
+            tracing::info!("first");
+            tracing::info!("second");
+        });
+        let groups = store.spans()[0].event_groups.clone();
+        let area = Rect::new(0, 0, 40, 5);
+        let mut buf = Buffer::empty(area);
+        let mut state = EventGroupListState::default();
+        EventGroupList::new(&groups).render(area, &mut buf, &mut state);
+        state.select(Some(1));
+        state.toggle_selected();
+
+        // the first group is evicted, so the second group moves to index 0
+        EventGroupList::new(&groups[1..]).render(area, &mut buf, &mut state);
+        assert!(state.is_expanded(&groups[1]));
+        assert!(!state.is_expanded(&groups[0]));
+    }
+}
@@ -0,0 +1,365 @@
+use std::{ops::Range, sync::Arc};
+
+use chrono::{DateTime, Local};
+use itertools::Itertools;
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::Modifier,
+    text::Line,
+    widgets::{Block, StatefulWidget, Widget},
+};
+use ratatui_macros::span;
+
+use super::{trace_tree::item_matches, TreeItem};
+use crate::{
+    display::{self, SpanFormat},
+    query::TraceQuery,
+    search::Search,
+    storage::{EventId, SpanKey, Spans, TraceStore},
+};
+
+/// A chronological list of the events from every span in a [`TraceStore`].
+///
+/// Each event is prefixed with the path of the span it was recorded in (e.g.
+/// `request:handler: message`), similar to the default `tracing-subscriber` output. When events are
+/// aggregated, each group is listed at the time of its most recent event.
+///
+/// See [`EventListState`] for scrolling the list. When a [`TraceQuery`] is set, only the matching
+/// events are listed, and when a [`Search`] is set, its matches are highlighted. Events are shown
+/// in the default [`SpanFormat`] unless another is set.
+#[derive(Debug, Clone)]
+pub struct EventListWidget<'a> {
+    store: &'a TraceStore,
+    block: Option<Block<'a>>,
+    query: Option<&'a TraceQuery>,
+    search: Option<&'a Search>,
+    format: Option<&'a SpanFormat>,
+}
+
+/// The state of an [`EventListWidget`].
+///
+/// Tracks the scroll offset and whether the list is following the newest events. Scrolling up
+/// stops following, and scrolling to the bottom starts following again.
+#[derive(Debug, Clone)]
+pub struct EventListState {
+    offset: usize,
+    follow: bool,
+    /// The number of events and the height from the last render.
+    len: usize,
+    height: usize,
+    /// The events from the last render.
+    entries: Entries,
+}
+
+impl Default for EventListState {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            follow: true,
+            len: 0,
+            height: 0,
+            entries: Entries::default(),
+        }
+    }
+}
+
+impl<'a> EventListWidget<'a> {
+    pub fn new(store: &'a TraceStore) -> Self {
+        Self {
+            store,
+            block: None,
+            query: None,
+            search: None,
+            format: None,
+        }
+    }
+
+    /// Wrap the list in a block.
+    pub fn block(mut self, block: Block<'a>) -> Self {
+        self.block = Some(block);
+        self
+    }
+
+    /// Only list the events matching the query.
+    pub fn query(mut self, query: &'a TraceQuery) -> Self {
+        self.query = Some(query);
+        self
+    }
+
+    /// Highlight the matches of the search.
+    pub fn search(mut self, search: &'a Search) -> Self {
+        self.search = Some(search);
+        self
+    }
+
+    /// Set how events are formatted.
+    pub fn format(mut self, format: &'a SpanFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+}
+
+impl EventListState {
+    /// Get the index of the first visible event.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns whether the list is following the newest events.
+    pub fn is_following(&self) -> bool {
+        self.follow
+    }
+
+    /// Start or stop following the newest events.
+    pub fn set_follow(&mut self, follow: bool) {
+        self.follow = follow;
+    }
+
+    /// Scroll up by the given number of events.
+    pub fn scroll_up(&mut self, amount: usize) {
+        self.offset = self.offset.min(self.max_offset()).saturating_sub(amount);
+        self.follow = false;
+    }
+
+    /// Scroll down by the given number of events.
+    pub fn scroll_down(&mut self, amount: usize) {
+        self.offset = self.offset.saturating_add(amount).min(self.max_offset());
+        self.follow = self.offset == self.max_offset();
+    }
+
+    /// Scroll up by a page.
+    pub fn page_up(&mut self) {
+        self.scroll_up(self.height.max(1));
+    }
+
+    /// Scroll down by a page.
+    pub fn page_down(&mut self) {
+        self.scroll_down(self.height.max(1));
+    }
+
+    /// Scroll to the oldest event.
+    pub fn scroll_to_top(&mut self) {
+        self.offset = 0;
+        self.follow = false;
+    }
+
+    /// Scroll to the newest event and follow new events.
+    pub fn scroll_to_bottom(&mut self) {
+        self.offset = self.max_offset();
+        self.follow = true;
+    }
+
+    fn max_offset(&self) -> usize {
+        self.len.saturating_sub(self.height)
+    }
+}
+
+impl StatefulWidget for EventListWidget<'_> {
+    type State = EventListState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let area = super::render_block(self.block, area, buf);
+        state
+            .entries
+            .update(self.store, self.query.filter(|query| !query.is_empty()));
+        let Entries { spans, entries, .. } = &state.entries;
+        state.len = entries.len();
+        state.height = area.height as usize;
+        state.offset = if state.follow {
+            state.max_offset()
+        } else {
+            state.offset.min(state.max_offset())
+        };
+
+        // only the visible events are formatted
+        let default_format = SpanFormat::default();
+        let format = self.format.unwrap_or(&default_format);
+        for (entry, row) in entries.iter().skip(state.offset).zip(area.rows()) {
+            let (line, searched) = entry.to_line(spans, format);
+            let line = match self.search {
+                Some(search)
+                    if item_matches(search, spans, TreeItem::Event(entry.span, entry.id)) =>
+                {
+                    search.highlight_spans(line, searched)
+                }
+                _ => line,
+            };
+            line.render(row, buf);
+        }
+    }
+}
+
+/// The events listed by the widget, kept between renders so that they are only collected and
+/// sorted again when the store or the query changes.
+#[derive(Debug, Clone, Default)]
+struct Entries {
+    /// The id and generation of the store, and the query, that the events were collected from.
+    built_from: Option<(usize, u64, Option<TraceQuery>)>,
+    /// The spans the events were collected from, shared with the store.
+    spans: Arc<Spans>,
+    entries: Vec<Entry>,
+}
+
+impl Entries {
+    /// Collects the events again if the store changed since they were collected, or the query
+    /// is different.
+    ///
+    /// The events are collected from a snapshot, so the store is not locked while they are
+    /// sorted or formatted.
+    fn update(&mut self, store: &TraceStore, query: Option<&TraceQuery>) {
+        // the generation is read before the snapshot, so a change in between is picked up by the
+        // next render
+        let built_from = (store.id(), store.generation(), query.cloned());
+        if self.built_from.as_ref() == Some(&built_from) {
+            return;
+        }
+        let spans = store.snapshot();
+        let mut entries = entries(&spans);
+        if let Some(query) = query {
+            entries.retain(|entry| {
+                spans.get(entry.span).is_some_and(|span| {
+                    query.matches_event_at(&spans, span, entry.span, entry.index)
+                })
+            });
+        }
+        self.entries = entries;
+        self.spans = Arc::new(spans);
+        self.built_from = Some(built_from);
+    }
+}
+
+/// An event (or event group) in a span, by its index in the span's events.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    time: DateTime<Local>,
+    span: SpanKey,
+    index: usize,
+    id: EventId,
+}
+
+impl Entry {
+    /// Formats the event, along with the range of the spans of the line that a search matches.
+    fn to_line<'a>(self, spans: &'a Spans, format: &SpanFormat) -> (Line<'a>, Range<usize>) {
+        let Some(span) = spans.get(self.span) else {
+            return (Line::default(), 0..0);
+        };
+        let (mut line, mut searched) = if span.event_groups.is_empty() {
+            format.format_event_with_searched(&span.events[self.index])
+        } else {
+            display::group_line_with_searched(&span.event_groups[self.index])
+        };
+        if self.span != SpanKey::ROOT {
+            let path = spans
+                .path(self.span)
+                .into_iter()
+                .map(|span| span.name.as_str())
+                .join(":");
+            // after the time and level
+            line.spans
+                .insert(3, span!(Modifier::DIM | Modifier::BOLD; " {path}:"));
+            searched = searched.start + 1..searched.end + 1;
+        }
+        (line, searched)
+    }
+}
+
+/// Returns the events from every span, oldest first.
+fn entries(spans: &Spans) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = spans
+        .iter()
+        .flat_map(|(key, span)| {
+            let times: Vec<_> = if span.event_groups.is_empty() {
+                span.events
+                    .iter()
+                    .map(|event| (event.time, event.id()))
+                    .collect()
+            } else {
+                span.event_groups
+                    .iter()
+                    .map(|group| (group.last_time, group.id()))
+                    .collect()
+            };
+            times
+                .into_iter()
+                .enumerate()
+                .map(move |(index, (time, id))| Entry {
+                    time,
+                    span: key,
+                    index,
+                    id,
+                })
+        })
+        .collect();
+    entries.sort_by_key(|entry| entry.time);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture, capture_with};
+
+    #[test]
+    fn events_are_interleaved_with_span_paths() {
+        let store = capture(|| {
+            tracing::info!("start");
+            tracing::info_span!("request").in_scope(|| {
+                tracing::info!("received");
+                tracing::info_span!("db").in_scope(|| tracing::info!("query"));
+                tracing::info!("responded");
+            });
+            tracing::info!("stop");
+        });
+
+        let spans = store.read();
+        let lines: Vec<String> = entries(&spans)
+            .into_iter()
+            .map(|entry| {
+                let (line, _) = entry.to_line(&spans, &SpanFormat::default());
+                // skip the time and level
+                line.spans[3..].iter().map(|span| &*span.content).collect()
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                " start",
+                " request: received",
+                " request:db: query",
+                " request: responded",
+                " stop",
+            ]
+        );
+    }
+
+    #[test]
+    fn events_are_listed_again_once_the_store_changes() {
+        let store = capture(|| tracing::info!("first"));
+        let rows = |state: &mut EventListState, format: &SpanFormat| {
+            let area = Rect::new(0, 0, 80, 3);
+            let mut buf = Buffer::empty(area);
+            EventListWidget::new(&store)
+                .format(format)
+                .render(area, &mut buf, state);
+            buf.content
+                .chunks(area.width as usize)
+                .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>())
+                .map(|row| row.trim_end().to_owned())
+                .filter(|row| !row.is_empty())
+                .collect::<Vec<_>>()
+        };
+
+        let mut state = EventListState::default();
+        let format = SpanFormat::default();
+        assert_eq!(rows(&mut state, &format).len(), 1);
+        let spans = Arc::clone(&state.entries.spans);
+        rows(&mut state, &format);
+        assert!(Arc::ptr_eq(&spans, &state.entries.spans));
+
+        capture_with(store.clone(), || tracing::info!("second"));
+        let rows = rows(&mut state, &SpanFormat::new().with_location(true));
+        assert_eq!(rows.len(), 2);
+        assert!(rows[1].contains(&format!("second at {}:", file!())));
+    }
+}
@@ -0,0 +1,113 @@
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Modifier},
+    text::Line,
+    widgets::{Block, StatefulWidget, Widget},
+};
+use ratatui_macros::{line, span};
+
+use crate::search::Search;
+
+/// A single line input for a [`Search`], showing the position of the selected match.
+///
+/// The text is edited through [`SearchBarState`], which builds the [`Search`] to pass to the
+/// widgets being searched.
+#[derive(Debug, Default, Clone)]
+pub struct SearchBar<'a> {
+    block: Option<Block<'a>>,
+    selected: Option<usize>,
+    count: Option<usize>,
+}
+
+/// The state of a [`SearchBar`].
+///
+/// Holds the search text and whether it is a regular expression or a substring.
+#[derive(Debug, Default, Clone)]
+pub struct SearchBarState {
+    input: String,
+    regex: bool,
+}
+
+impl<'a> SearchBar<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wrap the search bar in a block.
+    pub fn block(mut self, block: Block<'a>) -> Self {
+        self.block = Some(block);
+        self
+    }
+
+    /// Show the number of matches and the position of the selected match, if any.
+    pub fn matches(mut self, selected: Option<usize>, count: usize) -> Self {
+        self.selected = selected;
+        self.count = Some(count);
+        self
+    }
+}
+
+impl SearchBarState {
+    /// Get the search text.
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// Returns whether the text is searched as a regular expression.
+    pub fn is_regex(&self) -> bool {
+        self.regex
+    }
+
+    /// Switch between searching for a regular expression and a substring.
+    pub fn toggle_regex(&mut self) {
+        self.regex = !self.regex;
+    }
+
+    /// Add a character to the end of the search text.
+    pub fn push(&mut self, c: char) {
+        self.input.push(c);
+    }
+
+    /// Remove the last character of the search text.
+    pub fn pop(&mut self) {
+        self.input.pop();
+    }
+
+    /// Clear the search text.
+    pub fn clear(&mut self) {
+        self.input.clear();
+    }
+
+    /// Build the search, or `None` if the text is empty.
+    pub fn search(&self) -> Option<Result<Search, regex::Error>> {
+        if self.input.is_empty() {
+            None
+        } else if self.regex {
+            Some(Search::regex(&self.input))
+        } else {
+            Some(Ok(Search::substring(&self.input)))
+        }
+    }
+}
+
+impl StatefulWidget for SearchBar<'_> {
+    type State = SearchBarState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let area = super::render_block(self.block, area, buf);
+        let prefix = if state.regex { "regex/" } else { "/" };
+        line![span!(Modifier::DIM; prefix), state.input.as_str()].render(area, buf);
+
+        let status = match (state.search(), self.count) {
+            (Some(Err(_)), _) => Line::from(span!(Color::Red; "invalid regex")),
+            (Some(Ok(_)), Some(0)) => Line::from(span!(Modifier::DIM; "no matches")),
+            (Some(Ok(_)), Some(count)) => match self.selected {
+                Some(selected) => Line::from(span!(Modifier::DIM; "{}/{count}", selected + 1)),
+                None => Line::from(span!(Modifier::DIM; "{count} matches")),
+            },
+            _ => Line::default(),
+        };
+        status.right_aligned().render(area, buf);
+    }
+}
@@ -0,0 +1,332 @@
+use itertools::Itertools;
+use quanta::Instant;
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Modifier},
+    text::{Line, ToLine, ToSpan},
+    widgets::{Block, StatefulWidget, Widget},
+};
+use ratatui_macros::{line, span};
+
+use crate::{
+    display,
+    storage::{EventRecord, FieldValue, SpanKey, SpanRecord, Spans, TraceStore},
+};
+
+/// The full details of a single span in a [`TraceStore`].
+///
+/// Shows the span's metadata, every field, its timing breakdown, the chain of parent spans and
+/// every event recorded in it with their fields listed one per line. See [`SpanDetailState`] for
+/// scrolling the details.
+#[derive(Debug, Clone)]
+pub struct SpanDetailWidget<'a> {
+    store: &'a TraceStore,
+    key: SpanKey,
+    block: Option<Block<'a>>,
+}
+
+/// The state of a [`SpanDetailWidget`].
+#[derive(Debug, Default, Clone)]
+pub struct SpanDetailState {
+    offset: usize,
+    /// The number of lines and the height from the last render.
+    len: usize,
+    height: usize,
+}
+
+impl<'a> SpanDetailWidget<'a> {
+    pub fn new(store: &'a TraceStore, key: SpanKey) -> Self {
+        Self {
+            store,
+            key,
+            block: None,
+        }
+    }
+
+    /// Wrap the details in a block.
+    pub fn block(mut self, block: Block<'a>) -> Self {
+        self.block = Some(block);
+        self
+    }
+}
+
+impl SpanDetailState {
+    /// Get the index of the first visible line.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Scroll up by the given number of lines.
+    pub fn scroll_up(&mut self, amount: usize) {
+        self.offset = self.offset.min(self.max_offset()).saturating_sub(amount);
+    }
+
+    /// Scroll down by the given number of lines.
+    pub fn scroll_down(&mut self, amount: usize) {
+        self.offset = self.offset.saturating_add(amount).min(self.max_offset());
+    }
+
+    /// Scroll up by a page.
+    pub fn page_up(&mut self) {
+        self.scroll_up(self.height.max(1));
+    }
+
+    /// Scroll down by a page.
+    pub fn page_down(&mut self) {
+        self.scroll_down(self.height.max(1));
+    }
+
+    /// Scroll to the top of the details.
+    pub fn scroll_to_top(&mut self) {
+        self.offset = 0;
+    }
+
+    fn max_offset(&self) -> usize {
+        self.len.saturating_sub(self.height)
+    }
+}
+
+impl StatefulWidget for SpanDetailWidget<'_> {
+    type State = SpanDetailState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let area = super::render_block(self.block, area, buf);
+        let spans = self.store.read();
+        let lines = match spans.get(self.key) {
+            Some(span) => detail_lines(&spans, self.key, span),
+            None => vec![Line::from(span!(Modifier::DIM; "span not found"))],
+        };
+        state.len = lines.len();
+        state.height = area.height as usize;
+        state.offset = state.offset.min(state.max_offset());
+
+        for (line, row) in lines.into_iter().skip(state.offset).zip(area.rows()) {
+            line.render(row, buf);
+        }
+    }
+}
+
+fn detail_lines<'a>(spans: &'a Spans, key: SpanKey, span: &'a SpanRecord) -> Vec<Line<'a>> {
+    let mut lines = vec![
+        line![
+            span!(Modifier::BOLD; "{}", span.name),
+            " ",
+            span.level.to_span()
+        ],
+        property("Target", span.target.as_str()),
+        property("Location", span.location.to_string()),
+    ];
+    if let Some(module_path) = span.location.module_path {
+        lines.push(property("Module", module_path));
+    }
+    if let Some(thread) = &span.thread {
+        lines.push(property("Thread", thread.to_string()));
+        if let Some(task) = thread.task {
+            lines.push(property("Task", task.to_string()));
+        }
+    }
+    lines.extend([
+        property(
+            "Started",
+            span.start_time.format("%H:%M:%S%.3f").to_string(),
+        ),
+        property(
+            "Closed",
+            span.close_time.map_or("open".to_owned(), |time| {
+                time.format("%H:%M:%S%.3f").to_string()
+            }),
+        ),
+    ]);
+    let mut path = spans.path(key);
+    path.pop();
+    if !path.is_empty() {
+        let parents = path.iter().map(|span| span.name.as_str()).join(" → ");
+        lines.push(property("Parents", parents));
+    }
+
+    lines.push(heading("Fields"));
+    if span.fields.is_empty() {
+        lines.push(Line::from(span!(Modifier::DIM; "  none")));
+    }
+    for (name, value) in &span.fields {
+        push_field(&mut lines, name, value, 1);
+    }
+
+    let timing = &span.timing.snapshot(Instant::now());
+    let total = timing.total_duration();
+    let busy_percentage = display::percentage(timing.busy_duration(), total);
+    lines.extend([
+        heading("Timing"),
+        property(
+            "  Busy",
+            format!("{:.2?} ({busy_percentage:.1}%)", timing.busy_duration()),
+        ),
+        property(
+            "  Self busy",
+            format!("{:.2?}", timing.self_busy_duration()),
+        ),
+        property("  Idle", format!("{:.2?}", timing.idle_duration())),
+        property("  Total", format!("{total:.2?}")),
+        property("  Entered", timing.enter_count().to_string()),
+        property("  Exited", timing.exit_count().to_string()),
+        property("  Longest poll", format!("{:.2?}", timing.longest_poll())),
+    ]);
+    if timing.long_poll_count() > 0 {
+        lines.push(line![
+            span!(Modifier::DIM; "  Long polls: "),
+            span!(Color::Yellow; "⚠ {}", timing.long_poll_count()),
+        ]);
+    }
+
+    if span.event_groups.is_empty() {
+        lines.push(heading(format!("Events ({})", span.events.len())));
+        for event in &span.events {
+            push_event(&mut lines, event, 1);
+        }
+    } else {
+        let count: u64 = span.event_groups.iter().map(|group| group.count).sum();
+        lines.push(heading(format!("Events ({count})")));
+        for group in &span.event_groups {
+            let mut line = group.to_line();
+            line.spans.insert(0, "  ".into());
+            lines.push(line);
+            for event in &group.samples {
+                push_event(&mut lines, event, 2);
+            }
+        }
+    }
+    lines
+}
+
+/// Adds the event's time, level and message, followed by each of its other fields.
+fn push_event<'a>(lines: &mut Vec<Line<'a>>, event: &'a EventRecord, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let mut line = line![
+        indent,
+        span!(Modifier::DIM; "{}", event.time().format("%H:%M:%S%.3f")),
+        " ",
+        event.level().to_span(),
+        span!(" {}", event.message().unwrap_or_default()),
+    ];
+    if !event.location().is_unknown() {
+        line.push_span(span!(Modifier::DIM; " at {}", event.location()));
+    }
+    lines.push(line);
+    for (name, value) in event.fields() {
+        if name != "message" {
+            push_field(lines, name, value, depth + 1);
+        }
+    }
+}
+
+fn heading<'a>(title: impl Into<String>) -> Line<'a> {
+    Line::from(span!(Modifier::BOLD; "{}", title.into()))
+}
+
+fn property<'a>(name: &'a str, value: impl Into<String>) -> Line<'a> {
+    line![span!(Modifier::DIM; "{name}: "), value.into()]
+}
+
+/// Adds the field's name and value, followed by the sources of errors.
+fn push_field<'a>(lines: &mut Vec<Line<'a>>, name: &'a str, value: &'a FieldValue, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let mut value_lines = value.to_styled_text().lines.into_iter();
+    let mut line = line![
+        indent.clone(),
+        span!(Modifier::ITALIC; "{name}"),
+        span!(Modifier::DIM; " = "),
+    ];
+    line.extend(value_lines.next().into_iter().flat_map(|line| line.spans));
+    lines.push(line);
+    lines.extend(value_lines.map(|mut line| {
+        line.spans.insert(0, format!("{indent}  ").into());
+        line
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture;
+
+    #[test]
+    fn lists_fields_and_every_event() {
+        let store = capture(|| {
+            tracing::info_span!("request").in_scope(|| {
+                tracing::info_span!("db", user_id = 42).in_scope(|| {
+                    for i in 0..6 {
+                        tracing::info!(i, "polled");
+                    }
+                });
+            });
+        });
+        let keys: Vec<_> = store.tree().into_iter().map(|(_, key, _)| key).collect();
+        let [_root, _request, db] = keys[..] else {
+            panic!("expected three spans");
+        };
+
+        let spans = store.read();
+        let span = spans.get(db).unwrap();
+        let lines: Vec<String> = detail_lines(&spans, db, span)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert!(lines.contains(&"Parents: request".to_owned()));
+        let location = format!("Location: {}:", file!());
+        assert!(lines.iter().any(|line| line.starts_with(&location)));
+        assert!(lines.contains(&"  user_id = 42".to_owned()));
+        assert!(lines.contains(&"Events (6)".to_owned()));
+        assert!(lines.contains(&"    i = 5".to_owned()));
+    }
+
+    #[derive(Debug)]
+    struct Wrapped(&'static str, Option<Box<Wrapped>>);
+
+    impl std::fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl std::error::Error for Wrapped {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            self.1.as_deref().map(|source| source as _)
+        }
+    }
+
+    #[test]
+    fn errors_list_their_sources() {
+        let error = Wrapped(
+            "request failed",
+            Some(Box::new(Wrapped(
+                "query failed",
+                Some(Box::new(Wrapped("connection reset", None))),
+            ))),
+        );
+        let store = capture(|| {
+            tracing::info_span!("request").in_scope(|| {
+                tracing::error!(error = &error as &dyn std::error::Error, "failed");
+            });
+        });
+        let keys: Vec<_> = store.tree().into_iter().map(|(_, key, _)| key).collect();
+        let spans = store.read();
+        let span = spans.get(keys[1]).unwrap();
+        let lines: Vec<String> = detail_lines(&spans, keys[1], span)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let start = lines
+            .iter()
+            .position(|line| line == "    error = request failed")
+            .expect("error field is listed");
+        assert_eq!(
+            lines[start + 1..start + 4],
+            [
+                "      Caused by:",
+                "         0: query failed",
+                "         1: connection reset",
+            ]
+        );
+    }
+}
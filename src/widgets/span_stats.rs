@@ -0,0 +1,366 @@
+use std::{cmp::Ordering, time::Duration};
+
+use indexmap::IndexMap;
+use ratatui::{
+    buffer::Buffer,
+    layout::{Constraint, Rect},
+    style::{Modifier, Style},
+    text::Text,
+    widgets::{Block, Cell, Row, StatefulWidget, Table, TableState},
+};
+
+use crate::{display, stats::SpanStats, storage::TraceStore};
+
+/// A table of statistics for each span callsite (target and name) in a [`TraceStore`].
+///
+/// Lists the number of closed and active spans, the mean, p50, p99 and max of their total and
+/// busy durations, the busy percentage and the number of warning and error events. See
+/// [`SpanStatsTableState`] for sorting the table.
+#[derive(Debug, Clone)]
+pub struct SpanStatsTable<'a> {
+    store: &'a TraceStore,
+    block: Option<Block<'a>>,
+}
+
+/// A column of a [`SpanStatsTable`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatsColumn {
+    Name,
+    #[default]
+    Count,
+    Active,
+    TotalMean,
+    TotalP50,
+    TotalP99,
+    TotalMax,
+    BusyMean,
+    BusyP50,
+    BusyP99,
+    BusyMax,
+    BusyPercentage,
+    Warnings,
+    Errors,
+}
+
+/// The state of a [`SpanStatsTable`].
+///
+/// Tracks the selected row and the column the rows are sorted by. Rows are sorted in descending
+/// order by default, except by name.
+#[derive(Debug, Clone)]
+pub struct SpanStatsTableState {
+    table: TableState,
+    sort: StatsColumn,
+    descending: bool,
+    /// The number of rows from the last render.
+    len: usize,
+}
+
+impl Default for SpanStatsTableState {
+    fn default() -> Self {
+        Self {
+            table: TableState::default(),
+            sort: StatsColumn::default(),
+            descending: true,
+            len: 0,
+        }
+    }
+}
+
+impl<'a> SpanStatsTable<'a> {
+    pub fn new(store: &'a TraceStore) -> Self {
+        Self { store, block: None }
+    }
+
+    /// Wrap the table in a block.
+    pub fn block(mut self, block: Block<'a>) -> Self {
+        self.block = Some(block);
+        self
+    }
+}
+
+impl StatsColumn {
+    /// All of the columns, in the order they are shown.
+    pub const ALL: [StatsColumn; 14] = [
+        StatsColumn::Name,
+        StatsColumn::Count,
+        StatsColumn::Active,
+        StatsColumn::TotalMean,
+        StatsColumn::TotalP50,
+        StatsColumn::TotalP99,
+        StatsColumn::TotalMax,
+        StatsColumn::BusyMean,
+        StatsColumn::BusyP50,
+        StatsColumn::BusyP99,
+        StatsColumn::BusyMax,
+        StatsColumn::BusyPercentage,
+        StatsColumn::Warnings,
+        StatsColumn::Errors,
+    ];
+
+    /// Get the title of the column.
+    pub fn title(self) -> &'static str {
+        match self {
+            StatsColumn::Name => "Span",
+            StatsColumn::Count => "Count",
+            StatsColumn::Active => "Active",
+            StatsColumn::TotalMean => "Mean",
+            StatsColumn::TotalP50 => "p50",
+            StatsColumn::TotalP99 => "p99",
+            StatsColumn::TotalMax => "Max",
+            StatsColumn::BusyMean => "Busy mean",
+            StatsColumn::BusyP50 => "Busy p50",
+            StatsColumn::BusyP99 => "Busy p99",
+            StatsColumn::BusyMax => "Busy max",
+            StatsColumn::BusyPercentage => "Busy %",
+            StatsColumn::Warnings => "Warn",
+            StatsColumn::Errors => "Error",
+        }
+    }
+
+    fn index(self) -> usize {
+        Self::ALL
+            .iter()
+            .position(|&column| column == self)
+            .expect("all columns are listed")
+    }
+}
+
+impl SpanStatsTableState {
+    /// Get the index of the selected row.
+    pub fn selected(&self) -> Option<usize> {
+        self.table.selected()
+    }
+
+    /// Select the next row.
+    pub fn select_next(&mut self) {
+        let last = self.len.saturating_sub(1);
+        let next = self.table.selected().map_or(0, |i| i.saturating_add(1));
+        self.table.select(Some(next.min(last)));
+    }
+
+    /// Select the previous row.
+    pub fn select_previous(&mut self) {
+        let previous = self.table.selected().map_or(0, |i| i.saturating_sub(1));
+        self.table.select(Some(previous));
+    }
+
+    /// Get the column the rows are sorted by.
+    pub fn sort_column(&self) -> StatsColumn {
+        self.sort
+    }
+
+    /// Returns whether the rows are sorted in descending order.
+    pub fn is_descending(&self) -> bool {
+        self.descending
+    }
+
+    /// Sort the rows by the column, reversing the order if they are already sorted by it.
+    pub fn sort_by(&mut self, column: StatsColumn) {
+        if self.sort == column {
+            self.descending = !self.descending;
+        } else {
+            self.sort = column;
+            self.descending = column != StatsColumn::Name;
+        }
+    }
+
+    /// Sort the rows by the next column.
+    pub fn sort_by_next_column(&mut self) {
+        let index = (self.sort.index() + 1) % StatsColumn::ALL.len();
+        self.sort_by(StatsColumn::ALL[index]);
+    }
+
+    /// Sort the rows by the previous column.
+    pub fn sort_by_previous_column(&mut self) {
+        let len = StatsColumn::ALL.len();
+        let index = (self.sort.index() + len - 1) % len;
+        self.sort_by(StatsColumn::ALL[index]);
+    }
+}
+
+impl StatefulWidget for SpanStatsTable<'_> {
+    type State = SpanStatsTableState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let spans = self.store.read();
+        let mut rows: IndexMap<(&str, &str), StatsRow> = IndexMap::new();
+        for stats in spans.stats() {
+            let row = rows.entry((stats.target(), stats.name())).or_default();
+            row.stats = Some(stats);
+        }
+        for (_, span) in spans.user_spans() {
+            if span.close_time.is_none() {
+                let row = rows.entry((&span.target, &span.name)).or_default();
+                row.active += 1;
+            }
+        }
+        let mut rows: Vec<_> = rows
+            .into_iter()
+            .map(|((target, name), row)| StatsRow {
+                target,
+                name,
+                ..row
+            })
+            .collect();
+        rows.sort_by(|a, b| {
+            let ordering = a.compare(b, state.sort);
+            if state.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        state.len = rows.len();
+        if let Some(selected) = state.table.selected() {
+            state
+                .table
+                .select(Some(selected.min(rows.len().saturating_sub(1))));
+        }
+
+        let header = Row::new(StatsColumn::ALL.map(|column| {
+            let marker = match (column == state.sort, state.descending) {
+                (false, _) => "",
+                (true, true) => " ▼",
+                (true, false) => " ▲",
+            };
+            Cell::from(format!("{}{marker}", column.title()))
+        }))
+        .style(Style::new().add_modifier(Modifier::BOLD));
+        let selected = state.table.selected();
+        let rows = rows.iter().enumerate().map(|(index, row)| {
+            let row = Row::new(StatsColumn::ALL.map(|column| row.cell(column)));
+            if Some(index) == selected {
+                row.style(Style::new().add_modifier(Modifier::REVERSED))
+            } else {
+                row
+            }
+        });
+        let widths = StatsColumn::ALL.map(|column| match column {
+            StatsColumn::Name => Constraint::Fill(1),
+            StatsColumn::BusyPercentage | StatsColumn::Warnings | StatsColumn::Errors => {
+                Constraint::Length(7)
+            }
+            _ => Constraint::Length(10),
+        });
+        let mut table = Table::new(rows, widths).header(header);
+        if let Some(block) = self.block {
+            table = table.block(block);
+        }
+        StatefulWidget::render(table, area, buf, &mut state.table);
+    }
+}
+
+/// The statistics for a callsite, which may only have active spans.
+#[derive(Debug, Default)]
+struct StatsRow<'a> {
+    target: &'a str,
+    name: &'a str,
+    active: u64,
+    stats: Option<&'a SpanStats>,
+}
+
+impl StatsRow<'_> {
+    fn duration(&self, column: StatsColumn) -> Duration {
+        let Some(stats) = self.stats else {
+            return Duration::ZERO;
+        };
+        match column {
+            StatsColumn::TotalMean => stats.total_mean(),
+            StatsColumn::TotalP50 => stats.total_quantile(0.5),
+            StatsColumn::TotalP99 => stats.total_quantile(0.99),
+            StatsColumn::TotalMax => stats.total_max(),
+            StatsColumn::BusyMean => stats.busy_mean(),
+            StatsColumn::BusyP50 => stats.busy_quantile(0.5),
+            StatsColumn::BusyP99 => stats.busy_quantile(0.99),
+            StatsColumn::BusyMax => stats.busy_max(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    fn count(&self, column: StatsColumn) -> u64 {
+        match (column, self.stats) {
+            (StatsColumn::Active, _) => self.active,
+            (StatsColumn::Count, Some(stats)) => stats.count(),
+            (StatsColumn::Warnings, Some(stats)) => stats.warn_events(),
+            (StatsColumn::Errors, Some(stats)) => stats.error_events(),
+            _ => 0,
+        }
+    }
+
+    fn busy_percentage(&self) -> f64 {
+        display::percentage(
+            self.duration(StatsColumn::BusyMean),
+            self.duration(StatsColumn::TotalMean),
+        )
+    }
+
+    fn compare(&self, other: &Self, column: StatsColumn) -> Ordering {
+        match column {
+            StatsColumn::Name => (self.target, self.name).cmp(&(other.target, other.name)),
+            StatsColumn::Count
+            | StatsColumn::Active
+            | StatsColumn::Warnings
+            | StatsColumn::Errors => self.count(column).cmp(&other.count(column)),
+            StatsColumn::BusyPercentage => {
+                self.busy_percentage().total_cmp(&other.busy_percentage())
+            }
+            _ => self.duration(column).cmp(&other.duration(column)),
+        }
+    }
+
+    fn cell(&self, column: StatsColumn) -> Cell<'static> {
+        let text = match column {
+            StatsColumn::Name => format!("{}::{}", self.target, self.name),
+            StatsColumn::Count
+            | StatsColumn::Active
+            | StatsColumn::Warnings
+            | StatsColumn::Errors => self.count(column).to_string(),
+            StatsColumn::BusyPercentage => format!("{:.1}%", self.busy_percentage()),
+            _ => format!("{:.2?}", self.duration(column)),
+        };
+        match column {
+            StatsColumn::Name => Cell::from(text),
+            _ => Cell::from(Text::from(text).right_aligned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture;
+
+    #[test]
+    fn rows_are_sorted_by_the_selected_column() {
+        let store = capture(|| {
+            for _ in 0..3 {
+                drop(tracing::info_span!("query"));
+            }
+            drop(tracing::info_span!("connect"));
+            // still active
+            std::mem::forget(tracing::info_span!("server"));
+        });
+
+        let names = |state: &mut SpanStatsTableState| {
+            let area = Rect::new(0, 0, 240, 5);
+            let mut buf = Buffer::empty(area);
+            SpanStatsTable::new(&store).render(area, &mut buf, state);
+            (1..area.height)
+                .map(|y| {
+                    let row: String = (0..area.width).map(|x| buf[(x, y)].symbol()).collect();
+                    let name = row.split_whitespace().next().unwrap_or_default();
+                    name.rsplit("::").next().unwrap().to_owned()
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut state = SpanStatsTableState::default();
+        assert_eq!(names(&mut state), ["query", "connect", "server", ""]);
+        state.sort_by(StatsColumn::Active);
+        assert_eq!(names(&mut state)[0], "server");
+        state.sort_by(StatsColumn::Name);
+        assert!(!state.is_descending());
+        assert_eq!(names(&mut state), ["connect", "query", "server", ""]);
+        state.sort_by(StatsColumn::Name);
+        assert_eq!(names(&mut state), ["server", "query", "connect", ""]);
+    }
+}
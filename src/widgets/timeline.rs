@@ -0,0 +1,364 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Local, TimeDelta};
+use quanta::Instant;
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, StatefulWidget, Widget},
+};
+use ratatui_macros::span;
+
+use crate::storage::{SpanRecord, TraceStore};
+
+/// A timeline of the spans in a [`TraceStore`], drawing each span as a bar over its lifetime.
+///
+/// Spans are nested under their parent like in a [`TraceTreeWidget`](crate::TraceTreeWidget), with
+/// the span names on the left and the bars on a shared time axis on the right. Busy time is drawn
+/// solid and idle time dim, in the color of the span's level. Use
+/// [`TimingLayer::with_max_intervals`](crate::TimingLayer::with_max_intervals) to draw when each
+/// span was actually busy.
+///
+/// See [`TimelineState`] for zooming and panning the time axis.
+#[derive(Debug, Clone)]
+pub struct TimelineWidget<'a> {
+    store: &'a TraceStore,
+    block: Option<Block<'a>>,
+    label_width: u16,
+}
+
+/// The state of a [`TimelineWidget`].
+///
+/// By default the time axis fits every span and follows new spans as they are recorded. Zooming
+/// or panning fixes the visible time range until [`reset`](Self::reset) is called.
+#[derive(Debug, Default, Clone)]
+pub struct TimelineState {
+    /// The width of the visible time range, or `None` to fit every span.
+    window: Option<TimeDelta>,
+    /// The end of the visible time range, or `None` to follow the latest span.
+    end: Option<DateTime<Local>>,
+    offset: usize,
+    /// The time range, number of rows and height from the last render.
+    range: Option<(DateTime<Local>, DateTime<Local>)>,
+    /// The time from the start of the first span to the end of the last one, as of the last
+    /// render, which bounds zooming out.
+    extent: TimeDelta,
+    len: usize,
+    height: usize,
+}
+
+impl<'a> TimelineWidget<'a> {
+    pub fn new(store: &'a TraceStore) -> Self {
+        Self {
+            store,
+            block: None,
+            label_width: 24,
+        }
+    }
+
+    /// Wrap the timeline in a block.
+    pub fn block(mut self, block: Block<'a>) -> Self {
+        self.block = Some(block);
+        self
+    }
+
+    /// Set the width of the column of span names.
+    pub fn label_width(mut self, width: u16) -> Self {
+        self.label_width = width;
+        self
+    }
+}
+
+impl TimelineState {
+    /// Get the visible time range, as of the last render.
+    pub fn range(&self) -> Option<(DateTime<Local>, DateTime<Local>)> {
+        self.range
+    }
+
+    /// Halve the visible time range, keeping its end fixed.
+    pub fn zoom_in(&mut self) {
+        self.zoom(|window| window / 2);
+    }
+
+    /// Double the visible time range, keeping its end fixed.
+    ///
+    /// The range does not grow past the time covered by the spans.
+    pub fn zoom_out(&mut self) {
+        let extent = self.extent;
+        self.zoom(|window| {
+            let limit = extent.max(window);
+            window
+                .checked_mul(2)
+                .map_or(limit, |window| window.min(limit))
+        });
+    }
+
+    /// Move the visible time range back by a quarter of its width.
+    pub fn pan_left(&mut self) {
+        self.pan(|end, window| end.checked_sub_signed(window / 4));
+    }
+
+    /// Move the visible time range forward by a quarter of its width.
+    pub fn pan_right(&mut self) {
+        self.pan(|end, window| end.checked_add_signed(window / 4));
+    }
+
+    /// Fit every span in the time axis and follow new spans.
+    pub fn reset(&mut self) {
+        self.window = None;
+        self.end = None;
+    }
+
+    /// Scroll up by the given number of rows.
+    pub fn scroll_up(&mut self, amount: usize) {
+        self.offset = self.offset.min(self.max_offset()).saturating_sub(amount);
+    }
+
+    /// Scroll down by the given number of rows.
+    pub fn scroll_down(&mut self, amount: usize) {
+        self.offset = self.offset.saturating_add(amount).min(self.max_offset());
+    }
+
+    fn zoom(&mut self, f: impl FnOnce(TimeDelta) -> TimeDelta) {
+        let Some((start, end)) = self.range else {
+            return;
+        };
+        let window = f(end - start).max(TimeDelta::microseconds(1));
+        self.window = Some(window);
+        self.end = Some(end);
+    }
+
+    fn pan(&mut self, f: impl FnOnce(DateTime<Local>, TimeDelta) -> Option<DateTime<Local>>) {
+        let Some((start, end)) = self.range else {
+            return;
+        };
+        self.window = Some(end - start);
+        self.end = Some(f(end, end - start).unwrap_or(end));
+    }
+
+    fn max_offset(&self) -> usize {
+        self.len.saturating_sub(self.height)
+    }
+}
+
+impl StatefulWidget for TimelineWidget<'_> {
+    type State = TimelineState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let area = super::render_block(self.block, area, buf);
+        let now = Local::now();
+        let spans = self.store.snapshot();
+        let rows = spans.user_tree();
+        let Some(first) = rows.iter().map(|(_, _, span)| span.start_time).min() else {
+            state.range = None;
+            return;
+        };
+        let last = rows
+            .iter()
+            .map(|(_, _, span)| span.close_time.unwrap_or(now))
+            .max()
+            .unwrap_or(now);
+        let end = state.end.unwrap_or(last);
+        let start = state
+            .window
+            .and_then(|window| end.checked_sub_signed(window))
+            .unwrap_or(first);
+        state.range = Some((start, end));
+        state.extent = last - first;
+        state.len = rows.len();
+        state.height = area.height.saturating_sub(1) as usize;
+        state.offset = state.offset.min(state.max_offset());
+
+        let label_width = self.label_width.min(area.width);
+        let mut rows_area = area.rows();
+        let Some(axis_row) = rows_area.next() else {
+            return;
+        };
+        let axis_area = Rect {
+            x: axis_row.x + label_width,
+            width: axis_row.width - label_width,
+            ..axis_row
+        };
+        render_axis(start, end, axis_area, buf);
+
+        for ((depth, _, span), row) in rows.iter().skip(state.offset).zip(rows_area) {
+            let label = Line::from(format!("{}{}", "  ".repeat(*depth), span.name));
+            label.render(
+                Rect {
+                    width: label_width,
+                    ..row
+                },
+                buf,
+            );
+            let bar_area = Rect {
+                x: row.x + label_width,
+                width: row.width - label_width,
+                ..row
+            };
+            render_bar(span, now, (start, end), bar_area, buf);
+        }
+    }
+}
+
+/// Renders the start, width and end of the visible time range.
+fn render_axis(start: DateTime<Local>, end: DateTime<Local>, area: Rect, buf: &mut Buffer) {
+    let window = (end - start).to_std().unwrap_or_default();
+    Line::from(span!(Modifier::DIM; "{}", start.format("%H:%M:%S%.3f"))).render(area, buf);
+    Line::from(span!(Modifier::DIM; "{window:.2?}"))
+        .centered()
+        .render(area, buf);
+    Line::from(span!(Modifier::DIM; "{}", end.format("%H:%M:%S%.3f")))
+        .right_aligned()
+        .render(area, buf);
+}
+
+/// Renders the span's lifetime as a bar, with the busy intervals drawn solid.
+///
+/// When the span's busy intervals are not recorded, the busy time is drawn as a proportion at the
+/// start of the bar. The same applies to the part of the lifetime covered by intervals that were
+/// dropped from a full [`IntervalLog`](crate::IntervalLog).
+fn render_bar(
+    span: &SpanRecord,
+    now: DateTime<Local>,
+    range: (DateTime<Local>, DateTime<Local>),
+    area: Rect,
+    buf: &mut Buffer,
+) {
+    let end = span.close_time.unwrap_or(now);
+    let Some((x0, x1)) = columns(span.start_time, end, range, area.width) else {
+        return;
+    };
+    let color = span.level.color();
+    let idle = Style::new().fg(color).add_modifier(Modifier::DIM);
+    let busy = Style::new().fg(color);
+    let mut draw = |from: u16, to: u16, symbol: &str, style: Style| {
+        for x in from..to {
+            buf[(area.x + x, area.y)]
+                .set_symbol(symbol)
+                .set_style(style);
+        }
+    };
+    draw(x0, x1, "━", idle);
+
+    // include the current busy interval of spans that are still open
+    let timing = &span.timing.snapshot(Instant::now());
+    let Some(intervals) = timing.intervals() else {
+        let ratio = busy_ratio(timing.busy_duration(), timing.total_duration());
+        let busy_cells = ((x1 - x0) as f64 * ratio).round() as u16;
+        draw(x0, x0 + busy_cells, "█", busy);
+        return;
+    };
+    let at = |offset: Duration| span.start_time + TimeDelta::from_std(offset).unwrap_or_default();
+    let dropped = intervals.dropped();
+    if dropped.count > 0 {
+        if let Some((from, to)) = columns(span.start_time, at(dropped.end), range, area.width) {
+            let ratio = busy_ratio(dropped.busy, dropped.end);
+            draw(
+                from,
+                from + ((to - from) as f64 * ratio).round() as u16,
+                "█",
+                busy,
+            );
+        }
+    }
+    for interval in intervals.iter() {
+        if let Some((from, to)) = columns(at(interval.start), at(interval.end), range, area.width) {
+            draw(from, to, "█", busy);
+        }
+    }
+}
+
+fn busy_ratio(busy: Duration, total: Duration) -> f64 {
+    if total.is_zero() {
+        0.0
+    } else {
+        busy.as_secs_f64() / total.as_secs_f64()
+    }
+}
+
+/// Returns the columns covered by the time range `start..end` when `range` spans `width` columns,
+/// or `None` if it is outside of `range`.
+///
+/// Every visible span covers at least one column.
+fn columns(
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+    (range_start, range_end): (DateTime<Local>, DateTime<Local>),
+    width: u16,
+) -> Option<(u16, u16)> {
+    if end < range_start || start > range_end || width == 0 {
+        return None;
+    }
+    let window = (range_end - range_start)
+        .num_nanoseconds()
+        .unwrap_or(i64::MAX)
+        .max(1) as f64;
+    let column = |time: DateTime<Local>| {
+        let offset = (time - range_start).num_nanoseconds().unwrap_or(i64::MAX) as f64;
+        (offset / window * width as f64).clamp(0.0, width as f64) as u16
+    };
+    let x0 = column(start).min(width - 1);
+    let x1 = column(end).max(x0 + 1);
+    Some((x0, x1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_are_scaled_to_the_range() {
+        let start = Local::now();
+        let range = (start, start + TimeDelta::milliseconds(100));
+        let at = |ms| start + TimeDelta::milliseconds(ms);
+        assert_eq!(columns(at(0), at(100), range, 50), Some((0, 50)));
+        assert_eq!(columns(at(20), at(40), range, 50), Some((10, 20)));
+        // spans shorter than a column are still visible
+        assert_eq!(columns(at(50), at(50), range, 50), Some((25, 26)));
+        // spans are clipped to the range
+        assert_eq!(columns(at(-50), at(200), range, 50), Some((0, 50)));
+        assert_eq!(columns(at(150), at(200), range, 50), None);
+    }
+
+    #[test]
+    fn zoom_and_pan_fix_the_range() {
+        let start = Local::now();
+        let end = start + TimeDelta::milliseconds(800);
+        let mut state = TimelineState {
+            range: Some((start, end)),
+            ..TimelineState::default()
+        };
+        state.zoom_in();
+        assert_eq!(state.window, Some(TimeDelta::milliseconds(400)));
+        assert_eq!(state.end, Some(end));
+
+        state.range = Some((end - TimeDelta::milliseconds(400), end));
+        state.pan_left();
+        assert_eq!(state.end, Some(end - TimeDelta::milliseconds(100)));
+
+        state.reset();
+        assert_eq!((state.window, state.end), (None, None));
+    }
+
+    #[test]
+    fn zooming_out_stops_at_the_time_covered_by_the_spans() {
+        let store = crate::capture(|| {
+            tracing::info_span!("request").in_scope(|| {
+                std::thread::sleep(Duration::from_millis(2));
+            });
+        });
+        let area = Rect::new(0, 0, 40, 4);
+        let mut buf = Buffer::empty(area);
+        let mut state = TimelineState::default();
+        TimelineWidget::new(&store).render(area, &mut buf, &mut state);
+        let (start, end) = state.range().unwrap();
+        state.zoom_in();
+        for _ in 0..100 {
+            TimelineWidget::new(&store).render(area, &mut buf, &mut state);
+            state.zoom_out();
+        }
+        assert_eq!(state.window, Some(end - start));
+    }
+}
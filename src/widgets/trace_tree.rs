@@ -0,0 +1,795 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::Arc,
+};
+
+use chrono::{DateTime, Local};
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::Modifier,
+    text::Line,
+    widgets::{Block, StatefulWidget, Widget},
+};
+
+use crate::{
+    display::{self, SpanFormat},
+    query::TraceQuery,
+    search::Search,
+    storage::{EventId, SpanKey, Spans, TraceStore},
+};
+
+/// A tree of the spans in a [`TraceStore`], with the events of each span nested under it.
+///
+/// Events and child spans are listed under their parent in the order they happened. Spans can be
+/// collapsed to hide their contents, and the view can follow the newest rows as they are recorded.
+/// See [`TraceTreeState`] for navigating the tree.
+///
+/// When a [`TraceQuery`] is set, only the matching spans and events are shown, along with the
+/// ancestors of the matching spans so that they stay in context. When a [`Search`] is set, its
+/// matches are highlighted. Spans are shown in the default [`SpanFormat`] unless another is set.
+#[derive(Debug, Clone)]
+pub struct TraceTreeWidget<'a> {
+    store: &'a TraceStore,
+    block: Option<Block<'a>>,
+    query: Option<&'a TraceQuery>,
+    search: Option<&'a Search>,
+    format: Option<&'a SpanFormat>,
+}
+
+/// A row in a [`TraceTreeWidget`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TreeItem {
+    /// A span.
+    Span(SpanKey),
+    /// An event in a span, or an event group by the id of its first event when events are
+    /// aggregated.
+    Event(SpanKey, EventId),
+}
+
+impl TreeItem {
+    /// Get the key of the span, or of the span containing the event.
+    pub fn span(&self) -> SpanKey {
+        match *self {
+            TreeItem::Span(key) | TreeItem::Event(key, _) => key,
+        }
+    }
+}
+
+/// The state of a [`TraceTreeWidget`].
+///
+/// Tracks the selected row, which spans are collapsed, the scroll offset and whether the view is
+/// following the newest rows. Spans are expanded by default.
+///
+/// While following, the last row is selected and kept in view. Moving the selection stops
+/// following, and selecting the last row starts following again.
+#[derive(Debug, Clone)]
+pub struct TraceTreeState {
+    selected: Option<TreeItem>,
+    collapsed: HashSet<SpanKey>,
+    offset: usize,
+    follow: bool,
+    /// The visible rows and height from the last render, used to move the selection between
+    /// renders.
+    items: Vec<TreeItem>,
+    height: usize,
+    /// Every row from the last render including those inside collapsed spans.
+    rows: Rows,
+}
+
+impl Default for TraceTreeState {
+    fn default() -> Self {
+        Self {
+            selected: None,
+            collapsed: HashSet::new(),
+            offset: 0,
+            follow: true,
+            items: Vec::new(),
+            height: 0,
+            rows: Rows::default(),
+        }
+    }
+}
+
+impl<'a> TraceTreeWidget<'a> {
+    pub fn new(store: &'a TraceStore) -> Self {
+        Self {
+            store,
+            block: None,
+            query: None,
+            search: None,
+            format: None,
+        }
+    }
+
+    /// Wrap the tree in a block.
+    pub fn block(mut self, block: Block<'a>) -> Self {
+        self.block = Some(block);
+        self
+    }
+
+    /// Only show the spans and events matching the query.
+    pub fn query(mut self, query: &'a TraceQuery) -> Self {
+        self.query = Some(query);
+        self
+    }
+
+    /// Highlight the matches of the search.
+    pub fn search(mut self, search: &'a Search) -> Self {
+        self.search = Some(search);
+        self
+    }
+
+    /// Set how spans are formatted.
+    pub fn format(mut self, format: &'a SpanFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+}
+
+impl TraceTreeState {
+    /// Get the selected row.
+    pub fn selected(&self) -> Option<TreeItem> {
+        self.selected
+    }
+
+    /// Select the given row and stop following.
+    pub fn select(&mut self, item: Option<TreeItem>) {
+        self.selected = item;
+        self.follow = false;
+    }
+
+    /// Select the next row.
+    pub fn select_next(&mut self) {
+        self.move_selection(1);
+    }
+
+    /// Select the previous row.
+    pub fn select_previous(&mut self) {
+        self.move_selection(-1);
+    }
+
+    /// Move the selection down by a page.
+    pub fn page_down(&mut self) {
+        self.move_selection(self.height.max(1) as isize);
+    }
+
+    /// Move the selection up by a page.
+    pub fn page_up(&mut self) {
+        self.move_selection(-(self.height.max(1) as isize));
+    }
+
+    /// Select the first row.
+    pub fn select_first(&mut self) {
+        self.select(self.items.first().copied());
+    }
+
+    /// Select the last row and follow the newest rows.
+    pub fn select_last(&mut self) {
+        self.selected = self.items.last().copied();
+        self.follow = true;
+    }
+
+    /// Returns whether the view is following the newest rows.
+    pub fn is_following(&self) -> bool {
+        self.follow
+    }
+
+    /// Start or stop following the newest rows.
+    pub fn set_follow(&mut self, follow: bool) {
+        self.follow = follow;
+    }
+
+    /// Collapse the selected span if it is expanded, or expand it if it is collapsed.
+    ///
+    /// Does nothing if an event is selected.
+    pub fn toggle_selected(&mut self) {
+        if let Some(TreeItem::Span(key)) = self.selected {
+            if !self.collapsed.remove(&key) {
+                self.collapsed.insert(key);
+            }
+        }
+    }
+
+    /// Collapse the span, hiding its events and child spans.
+    pub fn collapse(&mut self, key: SpanKey) {
+        self.collapsed.insert(key);
+    }
+
+    /// Expand the span, showing its events and child spans.
+    pub fn expand(&mut self, key: SpanKey) {
+        self.collapsed.remove(&key);
+    }
+
+    /// Returns whether the span is collapsed.
+    pub fn is_collapsed(&self, key: SpanKey) -> bool {
+        self.collapsed.contains(&key)
+    }
+
+    /// Get the index of the first visible row.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Get the number of rows matching the search, as of the last render.
+    ///
+    /// Matches inside collapsed spans are counted too.
+    pub fn match_count(&self) -> usize {
+        self.rows.matches.len()
+    }
+
+    /// Get the position of the selected row among the rows matching the search.
+    pub fn selected_match(&self) -> Option<usize> {
+        let selected = self.row_index(self.selected?)?;
+        self.rows.matches.binary_search(&selected).ok()
+    }
+
+    /// Select the next row matching the search, wrapping around to the first match.
+    ///
+    /// The spans containing the match are expanded so that it is visible.
+    pub fn select_next_match(&mut self) {
+        let matches = &self.rows.matches;
+        let next = match self.selected.and_then(|item| self.row_index(item)) {
+            Some(selected) => matches.partition_point(|&index| index <= selected),
+            None => matches.partition_point(|&index| index < self.first_visible_row()),
+        };
+        if let Some(&index) = matches.get(next).or(matches.first()) {
+            self.select_row(index);
+        }
+    }
+
+    /// Select the previous row matching the search, wrapping around to the last match.
+    ///
+    /// The spans containing the match are expanded so that it is visible.
+    pub fn select_previous_match(&mut self) {
+        let current = self
+            .selected
+            .and_then(|item| self.row_index(item))
+            .unwrap_or_else(|| self.first_visible_row());
+        let matches = &self.rows.matches;
+        let previous = matches.partition_point(|&index| index < current);
+        let previous = previous
+            .checked_sub(1)
+            .and_then(|previous| matches.get(previous))
+            .or(matches.last());
+        if let Some(&index) = previous {
+            self.select_row(index);
+        }
+    }
+
+    fn selected_index(&self) -> Option<usize> {
+        let selected = self.selected?;
+        self.items.iter().position(|&item| item == selected)
+    }
+
+    /// Returns the index among every row of the given row.
+    fn row_index(&self, item: TreeItem) -> Option<usize> {
+        self.rows.index.get(&item).copied()
+    }
+
+    /// Returns the index among every row of the first visible row.
+    fn first_visible_row(&self) -> usize {
+        self.items
+            .get(self.offset)
+            .and_then(|&item| self.row_index(item))
+            .unwrap_or(0)
+    }
+
+    /// Selects the row at the given index among every row, expanding the spans containing it.
+    fn select_row(&mut self, index: usize) {
+        let Row {
+            item, mut parent, ..
+        } = self.rows.rows[index];
+        while let Some(index) = parent {
+            let row = &self.rows.rows[index];
+            self.collapsed.remove(&row.item.span());
+            parent = row.parent;
+        }
+        self.select(Some(item));
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let Some(last) = self.items.len().checked_sub(1) else {
+            return;
+        };
+        let index = match self.selected_index() {
+            Some(index) => index.saturating_add_signed(delta).min(last),
+            None => self.offset.min(last),
+        };
+        self.selected = Some(self.items[index]);
+        self.follow = false;
+    }
+}
+
+impl StatefulWidget for TraceTreeWidget<'_> {
+    type State = TraceTreeState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let area = super::render_block(self.block, area, buf);
+        state
+            .rows
+            .update(self.store, self.query.filter(|query| !query.is_empty()));
+        state.rows.search(self.search);
+        let all_rows = &state.rows.rows;
+        // a row is visible unless a span containing it is collapsed, and spans come before
+        // their contents
+        let mut visible = vec![false; all_rows.len()];
+        for (index, row) in all_rows.iter().enumerate() {
+            visible[index] = row.parent.is_none_or(|parent| {
+                visible[parent] && !state.collapsed.contains(&all_rows[parent].item.span())
+            });
+        }
+        let rows: Vec<(usize, &Row)> = all_rows
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| visible[index])
+            .collect();
+        state.items = rows.iter().map(|(_, row)| row.item).collect();
+        state.height = area.height as usize;
+
+        if state.follow {
+            state.selected = state.items.last().copied();
+        }
+        let selected = state.selected_index();
+        if selected.is_none() {
+            state.selected = None;
+        }
+        let height = state.height;
+        if state.follow {
+            state.offset = rows.len().saturating_sub(height);
+        } else if let Some(selected) = selected {
+            if selected < state.offset {
+                state.offset = selected;
+            } else if selected >= state.offset + height {
+                state.offset = selected + 1 - height;
+            }
+        }
+        state.offset = state.offset.min(rows.len().saturating_sub(height));
+
+        // only the visible rows are formatted
+        let default_format = SpanFormat::default();
+        let format = self.format.unwrap_or(&default_format);
+        let (spans, matches) = (&state.rows.spans, &state.rows.matches);
+        for ((position, &(index, row)), area) in
+            rows.iter().enumerate().skip(state.offset).zip(area.rows())
+        {
+            let collapsed = state.collapsed.contains(&row.item.span());
+            let (line, searched) = row.to_line(spans, format, collapsed);
+            let line = match self.search {
+                Some(search) if matches.binary_search(&index).is_ok() => {
+                    search.highlight_spans(line, searched)
+                }
+                _ => line,
+            };
+            let line = if Some(position) == selected {
+                line.patch_style(Modifier::REVERSED)
+            } else {
+                line
+            };
+            line.render(area, buf);
+        }
+    }
+}
+
+/// Every row of the tree including those inside collapsed spans, kept between renders so that
+/// they are only built again when the store or the query changes.
+#[derive(Debug, Clone, Default)]
+struct Rows {
+    /// The id and generation of the store, and the query, that the rows were built from.
+    built_from: Option<(usize, u64, Option<TraceQuery>)>,
+    /// The spans the rows were built from, shared with the store.
+    spans: Arc<Spans>,
+    rows: Vec<Row>,
+    /// The index of each row.
+    index: HashMap<TreeItem, usize>,
+    /// The pattern of the search the matches were found for, if they are up to date.
+    searched: Option<Option<String>>,
+    /// The indexes of the rows matching the search.
+    matches: Vec<usize>,
+}
+
+impl Rows {
+    /// Builds the rows again if the store changed since they were built, or the query is
+    /// different.
+    ///
+    /// The rows are built from a snapshot, so the store is not locked while they are built or
+    /// formatted.
+    fn update(&mut self, store: &TraceStore, query: Option<&TraceQuery>) {
+        // the generation is read before the snapshot, so a change in between is picked up by the
+        // next render
+        let built_from = (store.id(), store.generation(), query.cloned());
+        if self.built_from.as_ref() == Some(&built_from) {
+            return;
+        }
+        let spans = store.snapshot();
+        let filter = query.map(|query| Filter::new(query, &spans));
+        let mut rows = Vec::new();
+        for key in spans.roots() {
+            push_span(&mut rows, &spans, key, 0, None, filter.as_ref());
+        }
+        self.index = rows
+            .iter()
+            .enumerate()
+            .map(|(index, row)| (row.item, index))
+            .collect();
+        self.rows = rows;
+        self.spans = Arc::new(spans);
+        self.built_from = Some(built_from);
+        self.searched = None;
+    }
+
+    /// Finds the rows matching the search, unless they were found since the rows were built.
+    fn search(&mut self, search: Option<&Search>) {
+        let pattern = search.map(|search| search.pattern().to_owned());
+        if self.searched.as_ref() == Some(&pattern) {
+            return;
+        }
+        self.matches = match search {
+            Some(search) => self
+                .rows
+                .iter()
+                .enumerate()
+                .filter(|(_, row)| item_matches(search, &self.spans, row.item))
+                .map(|(index, _)| index)
+                .collect(),
+            None => Vec::new(),
+        };
+        self.searched = Some(pattern);
+    }
+}
+
+/// The spans and events to show when the tree is filtered by a query.
+struct Filter<'a> {
+    query: &'a TraceQuery,
+    /// The spans that match or contain matching events, and their ancestors.
+    visible: HashSet<SpanKey>,
+}
+
+impl<'a> Filter<'a> {
+    fn new(query: &'a TraceQuery, spans: &Spans) -> Self {
+        let mut visible = HashSet::new();
+        for (key, span) in spans.iter() {
+            let len = span.event_groups.len().max(span.events.len());
+            let matches = query.matches_span(spans, key)
+                || (0..len).any(|index| query.matches_event_at(spans, span, key, index));
+            if !matches {
+                continue;
+            }
+            let mut ancestor = Some(key);
+            while let Some(key) = ancestor {
+                if !visible.insert(key) {
+                    break;
+                }
+                ancestor = spans.get(key).and_then(|span| span.parent);
+            }
+        }
+        Self { query, visible }
+    }
+}
+
+/// A row in the tree, which is only formatted once it is known to be visible.
+#[derive(Debug, Clone)]
+struct Row {
+    item: TreeItem,
+    depth: usize,
+    /// Whether the row is a span with events or child spans to show.
+    has_contents: bool,
+    /// The index of the row of the span containing this row.
+    parent: Option<usize>,
+}
+
+impl Row {
+    /// Formats the row, along with the range of the spans of the line that a search matches.
+    fn to_line<'a>(
+        &self,
+        spans: &'a Spans,
+        format: &SpanFormat,
+        collapsed: bool,
+    ) -> (Line<'a>, Range<usize>) {
+        let Some(span) = spans.get(self.item.span()) else {
+            return (Line::default(), 0..0);
+        };
+        let line = match self.item {
+            TreeItem::Span(_) => Some(format.format_with_searched(span)),
+            TreeItem::Event(_, id) if span.event_groups.is_empty() => span
+                .event(id)
+                .map(|event| format.format_event_with_searched(event)),
+            TreeItem::Event(_, id) => span.event_group(id).map(display::group_line_with_searched),
+        };
+        let marker = match (self.has_contents, collapsed) {
+            (false, _) => "  ",
+            (true, true) => "▸ ",
+            (true, false) => "▾ ",
+        };
+        let (mut line, searched) = line.unwrap_or_default();
+        line.spans
+            .insert(0, format!("{}{marker}", indent(self.depth)).into());
+        (line, searched.start + 1..searched.end + 1)
+    }
+}
+
+/// Adds the rows for a span, followed by its events and child spans in time order.
+///
+/// The contents of collapsed spans are added too, so that they can be searched.
+fn push_span(
+    rows: &mut Vec<Row>,
+    spans: &Spans,
+    key: SpanKey,
+    depth: usize,
+    parent: Option<usize>,
+    filter: Option<&Filter>,
+) {
+    let Some(span) = spans.get(key) else {
+        return;
+    };
+    if filter.is_some_and(|filter| !filter.visible.contains(&key)) {
+        return;
+    }
+    let event_times: Vec<(usize, EventId, DateTime<Local>)> = if span.event_groups.is_empty() {
+        span.events
+            .iter()
+            .enumerate()
+            .map(|(index, event)| (index, event.id(), event.time))
+            .collect()
+    } else {
+        span.event_groups
+            .iter()
+            .enumerate()
+            .map(|(index, group)| (index, group.id(), group.first_time))
+            .collect()
+    };
+    let event_times: Vec<_> = event_times
+        .into_iter()
+        .filter(|&(index, _, _)| {
+            filter.is_none_or(|filter| filter.query.matches_event_at(spans, span, key, index))
+        })
+        .collect();
+    let children: Vec<_> = span
+        .children
+        .iter()
+        .filter(|child| filter.is_none_or(|filter| filter.visible.contains(child)))
+        .filter_map(|&child| spans.get(child).map(|span| (child, span.start_time)))
+        .collect();
+
+    let index = rows.len();
+    rows.push(Row {
+        item: TreeItem::Span(key),
+        depth,
+        has_contents: !event_times.is_empty() || !children.is_empty(),
+        parent,
+    });
+
+    let mut events = event_times.into_iter().peekable();
+    let mut children = children.into_iter().peekable();
+    loop {
+        let take_event = match (events.peek(), children.peek()) {
+            (Some((_, _, event_time)), Some((_, child_time))) => event_time <= child_time,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+        if take_event {
+            let (_, id, _) = events.next().expect("peeked");
+            rows.push(Row {
+                item: TreeItem::Event(key, id),
+                depth: depth + 1,
+                has_contents: false,
+                parent: Some(index),
+            });
+        } else {
+            let (child, _) = children.next().expect("peeked");
+            push_span(rows, spans, child, depth + 1, Some(index), filter);
+        }
+    }
+}
+
+/// Returns whether the span or event in the row matches the search.
+pub(super) fn item_matches(search: &Search, spans: &Spans, item: TreeItem) -> bool {
+    let Some(span) = spans.get(item.span()) else {
+        return false;
+    };
+    match item {
+        TreeItem::Span(_) => search.matches_span(span),
+        TreeItem::Event(_, id) if span.event_groups.is_empty() => span
+            .event(id)
+            .is_some_and(|event| search.matches_event(event)),
+        TreeItem::Event(_, id) => span
+            .event_group(id)
+            .is_some_and(|group| search.matches_group(group)),
+    }
+}
+
+fn indent(depth: usize) -> String {
+    "  ".repeat(depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::style::Color;
+
+    use super::*;
+    use crate::{capture, capture_with, RetentionPolicy};
+
+    #[test]
+    fn events_and_children_are_interleaved() {
+        let store = capture(|| {
+            tracing::info_span!("parent").in_scope(|| {
+                tracing::info!("before");
+                tracing::info_span!("child").in_scope(|| tracing::info!("inside"));
+                tracing::info!("after");
+            });
+        });
+        let keys: Vec<_> = store.tree().into_iter().map(|(_, key, _)| key).collect();
+        let [_root, parent, child] = keys[..] else {
+            panic!("expected three spans");
+        };
+        let event = |key, index| TreeItem::Event(key, store.get(key).unwrap().events[index].id());
+
+        let mut state = TraceTreeState::default();
+        let area = Rect::new(0, 0, 80, 10);
+        let mut buf = Buffer::empty(area);
+        TraceTreeWidget::new(&store).render(area, &mut buf, &mut state);
+        assert_eq!(
+            state.items,
+            [
+                TreeItem::Span(SpanKey::ROOT),
+                TreeItem::Span(parent),
+                event(parent, 0),
+                TreeItem::Span(child),
+                event(child, 0),
+                event(parent, 1),
+            ]
+        );
+        assert_eq!(state.selected(), Some(event(parent, 1)));
+
+        state.select(Some(TreeItem::Span(parent)));
+        state.toggle_selected();
+        TraceTreeWidget::new(&store).render(area, &mut buf, &mut state);
+        assert_eq!(
+            state.items,
+            [TreeItem::Span(SpanKey::ROOT), TreeItem::Span(parent)]
+        );
+    }
+
+    #[test]
+    fn search_navigates_between_matches() {
+        let store = capture(|| {
+            tracing::info_span!("request").in_scope(|| {
+                tracing::info!("cache miss");
+                tracing::info!("loaded");
+                tracing::info!("cache hit");
+            });
+        });
+        let keys: Vec<_> = store.tree().into_iter().map(|(_, key, _)| key).collect();
+        let [_root, request] = keys[..] else {
+            panic!("expected two spans");
+        };
+        let event =
+            |index| TreeItem::Event(request, store.get(request).unwrap().events[index].id());
+
+        let search = Search::substring("cache");
+        let mut state = TraceTreeState::default();
+        let area = Rect::new(0, 0, 80, 10);
+        let mut buf = Buffer::empty(area);
+        TraceTreeWidget::new(&store)
+            .search(&search)
+            .render(area, &mut buf, &mut state);
+        assert_eq!(state.match_count(), 2);
+        // following selects the last row, which is the last match
+        assert_eq!(state.selected_match(), Some(1));
+
+        state.select_next_match();
+        assert_eq!(state.selected(), Some(event(0)));
+        assert_eq!(state.selected_match(), Some(0));
+        state.select_next_match();
+        assert_eq!(state.selected(), Some(event(2)));
+        state.select_previous_match();
+        assert_eq!(state.selected(), Some(event(0)));
+    }
+
+    #[test]
+    fn selected_events_survive_older_events_being_removed() {
+        let store = TraceStore::default()
+            .with_retention(RetentionPolicy::new().with_max_events_per_span(2));
+        let area = Rect::new(0, 0, 80, 10);
+        let mut buf = Buffer::empty(area);
+        let mut state = TraceTreeState::default();
+        capture_with(store.clone(), || {
+            tracing::info!("first");
+            tracing::info!("second");
+            TraceTreeWidget::new(&store).render(area, &mut buf, &mut state);
+            // the last event is selected, which is the second event in the span
+            state.set_follow(false);
+            tracing::info!("third");
+        });
+        let selected = state.selected();
+        TraceTreeWidget::new(&store).render(area, &mut buf, &mut state);
+        assert_eq!(state.selected(), selected);
+        let Some(TreeItem::Event(key, id)) = selected else {
+            panic!("expected an event to be selected");
+        };
+        let event = store.get(key).unwrap().event(id).cloned().unwrap();
+        assert_eq!(event.message(), Some("second"));
+    }
+
+    #[test]
+    fn search_expands_spans_to_reach_matches() {
+        let store = capture(|| {
+            tracing::info_span!("request").in_scope(|| {
+                tracing::info_span!("db").in_scope(|| tracing::info!("cache miss"));
+            });
+        });
+        let keys: Vec<_> = store.tree().into_iter().map(|(_, key, _)| key).collect();
+        let [_root, request, db] = keys[..] else {
+            panic!("expected three spans");
+        };
+
+        let search = Search::substring("cache");
+        let mut state = TraceTreeState::default();
+        state.collapse(request);
+        state.collapse(db);
+        let area = Rect::new(0, 0, 80, 10);
+        let mut buf = Buffer::empty(area);
+        TraceTreeWidget::new(&store)
+            .search(&search)
+            .render(area, &mut buf, &mut state);
+        assert_eq!(state.match_count(), 1);
+
+        state.select_next_match();
+        assert!(!state.is_collapsed(request));
+        assert!(!state.is_collapsed(db));
+        let event = store.get(db).unwrap().events[0].id();
+        assert_eq!(state.selected(), Some(TreeItem::Event(db, event)));
+        TraceTreeWidget::new(&store)
+            .search(&search)
+            .render(area, &mut buf, &mut state);
+        assert_eq!(state.selected_match(), Some(0));
+        assert_eq!(state.items.last(), Some(&TreeItem::Event(db, event)));
+    }
+
+    #[test]
+    fn rows_are_built_again_only_when_the_store_or_query_changes() {
+        let store = capture(|| tracing::info_span!("request").in_scope(|| {}));
+        let mut state = TraceTreeState::default();
+        let area = Rect::new(0, 0, 80, 10);
+        let mut buf = Buffer::empty(area);
+        TraceTreeWidget::new(&store).render(area, &mut buf, &mut state);
+        let spans = Arc::clone(&state.rows.spans);
+        TraceTreeWidget::new(&store).render(area, &mut buf, &mut state);
+        assert!(Arc::ptr_eq(&spans, &state.rows.spans));
+
+        let query = TraceQuery::new().with_span_name("db");
+        TraceTreeWidget::new(&store)
+            .query(&query)
+            .render(area, &mut buf, &mut state);
+        assert_eq!(state.items, []);
+
+        capture_with(store.clone(), || tracing::info_span!("db").in_scope(|| {}));
+        TraceTreeWidget::new(&store)
+            .query(&query)
+            .render(area, &mut buf, &mut state);
+        assert_eq!(state.items.len(), 1);
+        assert!(!Arc::ptr_eq(&spans, &state.rows.spans));
+    }
+
+    #[test]
+    fn search_only_highlights_the_text_it_matches() {
+        let store = capture(|| tracing::info_span!("busy").in_scope(|| {}));
+        let search = Search::substring("busy");
+        let mut state = TraceTreeState::default();
+        let area = Rect::new(0, 0, 120, 2);
+        let mut buf = Buffer::empty(area);
+        TraceTreeWidget::new(&store)
+            .search(&search)
+            .render(area, &mut buf, &mut state);
+        let highlighted: String = buf
+            .content
+            .iter()
+            .filter(|cell| cell.bg == Color::Yellow)
+            .map(|cell| cell.symbol())
+            .collect();
+        // the timing columns also read "Busy", but are not what the search matched
+        assert_eq!(highlighted, "busy");
+    }
+}